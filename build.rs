@@ -12,19 +12,123 @@ macro_rules! plaintext_sources {
     };
 }
 
+macro_rules! dns_sources {
+    () => {
+        include!("includes/dns_sources")
+    };
+}
+
+macro_rules! stun_sources {
+    () => {
+        include!("includes/stun_sources")
+    };
+}
+
 macro_rules! json_sources {
     () => {
         include!("includes/json_sources")
     };
 }
 
+macro_rules! regex_sources {
+    () => {
+        include!("includes/regex_sources")
+    };
+}
+
 async fn make_default_sources_toml() -> io::Result<()> {
     let mut data = String::new();
 
+    writeln!(
+        data,
+        "# only accept an address once `agree` out of `query` sources returned it"
+    )
+    .unwrap();
+    writeln!(data, "# quorum = {{ query = 3, agree = 2 }}\n").unwrap();
+
+    writeln!(
+        data,
+        "# sources with a lower `tier` are asked first, the next tier only if none of them answered,"
+    )
+    .unwrap();
+    writeln!(
+        data,
+        "# sources without a tier are the last resort, each tier can resolve `concurrent-resolve` sources at once"
+    )
+    .unwrap();
+    writeln!(data, "# [tiers.1]\n# concurrent-resolve = 1\n").unwrap();
+
+    writeln!(
+        data,
+        "# the router itself can be asked with `upnp://`, `natpmp://<gateway>` or `pcp://<gateway>`"
+    )
+    .unwrap();
+    writeln!(data, "# [\"upnp://\"]\n# tier = 1\n# steps = []\n").unwrap();
+
+    writeln!(
+        data,
+        "# or read straight from an interface, when the public address is bound locally"
+    )
+    .unwrap();
+    writeln!(data, "# [wan]\n# interface = \"ppp0\"\n").unwrap();
+    writeln!(
+        data,
+        "# `exec = [\"program\", \"args\"]` and `file = \"/path\"` sources feed their output into the steps"
+    )
+    .unwrap();
+    writeln!(
+        data,
+        "# [modem]\n# exec = [\"/usr/local/bin/wan-ip\"]\n# timeout = 00:00:05\n"
+    )
+    .unwrap();
+    writeln!(
+        data,
+        "# http sources can set `method`, `headers`, `basic-auth`, their own `timeout` and `max-body-size` in bytes"
+    )
+    .unwrap();
+    writeln!(
+        data,
+        "# [\"https://ip.example.com\"]\n# headers = {{ x-api-key = \"...\" }}\n# basic-auth = {{ username = \"me\", password = \"...\" }}\n# timeout = 00:00:02\n"
+    )
+    .unwrap();
+
+    // opt in, so the default set keeps asking the same services
+    writeln!(
+        data,
+        "# dns sources are cheaper and survive http outages, uncomment them to ask them as well"
+    )
+    .unwrap();
+    for source in dns_sources!() {
+        writeln!(data, "# [\"{source}\"]").unwrap();
+        writeln!(
+            data,
+            "# steps = [\"Trim\", {{ Line = {{ index = 0 }} }}, \"Trim\"]\n"
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        data,
+        "# stun sources work where http echo services can't be reached, uncomment them to ask them as well"
+    )
+    .unwrap();
+    for source in stun_sources!() {
+        writeln!(data, "# [\"{source}\"]").unwrap();
+        writeln!(
+            data,
+            "# steps = [\"Trim\", {{ Line = {{ index = 0 }} }}, \"Trim\"]\n"
+        )
+        .unwrap();
+    }
+
     let plain_sources = plaintext_sources!();
     for source in plain_sources {
         writeln!(data, r#"["{source}"]"#).unwrap();
-        writeln!(data, "steps = [\"Plaintext\"]\n").unwrap();
+        writeln!(
+            data,
+            "steps = [\"Trim\", {{ Line = {{ index = 0 }} }}, \"Trim\"]\n"
+        )
+        .unwrap();
     }
 
     let plain_sources = json_sources!();
@@ -33,6 +137,12 @@ async fn make_default_sources_toml() -> io::Result<()> {
         writeln!(data, r#"steps = [{{ Json = {{ key = "{key}" }} }}]"#).unwrap();
     }
 
+    let regex_sources = regex_sources!();
+    for (source, pattern) in regex_sources {
+        writeln!(data, "\n[\"{source}\"]").unwrap();
+        writeln!(data, "steps = [{{ Regex = {{ pattern = '{pattern}' }} }}]").unwrap();
+    }
+
     tokio::fs::write("includes/sources.toml", data.trim()).await
 }
 
@@ -70,18 +180,38 @@ async fn make_default_sources_rs() -> io::Result<()> {
         };
     }
 
-    let mut sources = plaintext_sources!().map(|url| (url, vec![])).to_vec();
+    // tolerates surrounding whitespace, crlf line endings and anything after the first line
+    let plaintext_steps = vec![
+        DisplayStr("ProcessStep::Trim".to_owned()),
+        DisplayStr("ProcessStep::Line { index: 0 }".to_owned()),
+        DisplayStr("ProcessStep::Trim".to_owned()),
+    ];
+
+    let mut sources = plaintext_sources!()
+        .into_iter()
+        .map(|url| (url, plaintext_steps.clone()))
+        .collect::<Vec<_>>();
 
     sources.extend(json_sources!().map(|(source, key)| {
         (
             source,
             vec![format!(
-                r#"ProcessStep::Json {{ key: "{}".into() }}"#,
+                r#"ProcessStep::Json {{ key: JsonPath::parse("{}").unwrap_or_else(|_| abort_unreachable!("bad build artifact")) }}"#,
                 key.escape_debug()
             )],
         )
     }));
 
+    sources.extend(regex_sources!().map(|(source, pattern)| {
+        (
+            source,
+            vec![format!(
+                r#"ProcessStep::Regex {{ pattern: Pattern::new("{}").unwrap_or_else(|_| abort_unreachable!("bad build artifact")), group: None }}"#,
+                pattern.escape_debug()
+            )],
+        )
+    }));
+
     file.write_all(format!("{sources:?}").0.as_bytes()).await?;
 
     file.flush().await
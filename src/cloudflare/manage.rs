@@ -0,0 +1,131 @@
+use super::{Cloudflare, CloudflareError, DnsRecord, RecordType, Result};
+use serde::{Deserialize, Serialize};
+
+impl CloudflareError {
+    /// whether the api rejected the request with the given error code
+    pub fn has_code(&self, code: u32) -> bool {
+        matches!(self, CloudflareError::Api { errors, .. } if errors.0.iter().any(|e| e.code == code))
+    }
+}
+
+/// body of `POST zones/:zone_id/dns_records`
+#[derive(Debug, Clone, Serialize)]
+pub struct NewRecord<'a> {
+    #[serde(rename = "type")]
+    pub kind: RecordType,
+    pub name: &'a str,
+    pub content: &'a str,
+    /// 1 means automatic
+    pub ttl: u32,
+    pub proxied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub tags: &'a [&'a str],
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeletedRecord {
+    pub id: Box<str>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ZoneAccount {
+    pub id: Box<str>,
+    #[serde(default)]
+    pub name: Option<Box<str>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Zone {
+    pub id: Box<str>,
+    pub name: Box<str>,
+    #[serde(default)]
+    pub status: Option<Box<str>>,
+    #[serde(default)]
+    pub paused: bool,
+    pub account: ZoneAccount,
+    #[serde(default)]
+    pub name_servers: Vec<Box<str>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneType {
+    #[default]
+    Full,
+    Partial,
+    Secondary,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NewZoneAccount<'a> {
+    pub id: &'a str,
+}
+
+/// body of `POST zones`
+#[derive(Debug, Clone, Serialize)]
+pub struct NewZone<'a> {
+    pub account: NewZoneAccount<'a>,
+    pub name: &'a str,
+    #[serde(rename = "type")]
+    pub kind: ZoneType,
+}
+
+/// body of `PATCH zones/:zone_id`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PatchZone {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused: Option<bool>,
+}
+
+/// query of `GET zones`
+#[derive(Debug, Clone, Default)]
+pub struct ZoneQuery<'a> {
+    pub name: Option<&'a str>,
+    pub account_id: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeletedZone {
+    pub id: Box<str>,
+}
+
+impl<'a> Cloudflare<'a> {
+    pub async fn create_record(&self, zone_id: &str, record: &NewRecord<'_>) -> Result<DnsRecord> {
+        let url = self.url(["zones", zone_id, "dns_records"], [])?;
+        self.send_json(self.client.post(url), record).await
+    }
+
+    pub async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<DeletedRecord> {
+        let url = self.url(["zones", zone_id, "dns_records", record_id], [])?;
+        self.send(self.client.delete(url)).await
+    }
+
+    pub async fn list_zones(&self, query: &ZoneQuery<'_>) -> Result<Vec<Zone>> {
+        let params = [("name", query.name), ("account.id", query.account_id)];
+        let params = params.into_iter().filter_map(|(k, v)| Some((k, v?)));
+
+        self.list_all(self.url(["zones"], params)?).await
+    }
+
+    pub async fn get_zone(&self, zone_id: &str) -> Result<Zone> {
+        let url = self.url(["zones", zone_id], [])?;
+        self.send(self.client.get(url)).await
+    }
+
+    pub async fn create_zone(&self, zone: &NewZone<'_>) -> Result<Zone> {
+        let url = self.url(["zones"], [])?;
+        self.send_json(self.client.post(url), zone).await
+    }
+
+    pub async fn patch_zone(&self, zone_id: &str, patch: &PatchZone) -> Result<Zone> {
+        let url = self.url(["zones", zone_id], [])?;
+        self.send_json(self.client.patch(url), patch).await
+    }
+
+    pub async fn delete_zone(&self, zone_id: &str) -> Result<DeletedZone> {
+        let url = self.url(["zones", zone_id], [])?;
+        self.send(self.client.delete(url)).await
+    }
+}
@@ -0,0 +1,239 @@
+use crate::config::Config;
+use crate::retrying_client::{RequestBuilder, RetryingClient};
+use crate::tls::PinMismatch;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+use url::Url;
+
+/// the zones api and creating or deleting records, the daemon only lists and patches records
+#[allow(
+    dead_code,
+    reason = "kept as part of the typed api, nothing in the daemon calls it"
+)]
+pub mod manage;
+mod models;
+
+pub use models::*;
+
+/// the `errors` array of a failed response
+#[derive(Debug)]
+pub struct ApiErrors(pub Box<[ApiMessage]>);
+
+impl Display for ApiErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut errors = self.0.iter();
+        if let Some(first) = errors.next() {
+            write!(f, "{first}")?;
+        }
+        for err in errors {
+            write!(f, ", {err}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CloudflareError {
+    #[error(transparent)]
+    PinMismatch(PinMismatch),
+    #[error(transparent)]
+    Http(reqwest::Error),
+    #[error("unable to serialize the request body: {0}")]
+    Serialize(serde_json::Error),
+    #[error("invalid url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("cloudflare api error ({status}): {errors}")]
+    Api {
+        status: StatusCode,
+        errors: ApiErrors,
+    },
+    #[error("unable to deserialize the cloudflare response ({status}): {err}\n{body}")]
+    InvalidResponse {
+        status: StatusCode,
+        err: serde_json::Error,
+        body: Box<str>,
+    },
+    #[error("cloudflare reported success without a result")]
+    MissingResult,
+    #[error("gave up listing after {0} pages")]
+    TooManyPages(u32),
+    #[error("asked for page {requested} of a listing but got page {received}")]
+    UnexpectedPage { requested: u32, received: u32 },
+}
+
+impl From<reqwest::Error> for CloudflareError {
+    fn from(err: reqwest::Error) -> Self {
+        match PinMismatch::find(&err) {
+            Some(pin) => CloudflareError::PinMismatch(pin.clone()),
+            None => CloudflareError::Http(err),
+        }
+    }
+}
+
+pub type Result<T, E = CloudflareError> = std::result::Result<T, E>;
+
+/// cloudflare defaults to 100, fewer pages means fewer requests
+const RECORDS_PER_PAGE: u32 = 1000;
+/// a listing that goes on longer than this is a misbehaving api rather than a big zone
+const MAX_PAGES: u32 = 100;
+
+/// typed access to the cloudflare v4 api, borrows the client and config of the current run
+pub struct Cloudflare<'a> {
+    client: &'a RetryingClient,
+    cfg: &'a Config,
+}
+
+impl<'a> Cloudflare<'a> {
+    pub fn new(client: &'a RetryingClient, cfg: &'a Config) -> Self {
+        Cloudflare { client, cfg }
+    }
+
+    fn url<'s>(
+        &self,
+        segments: impl IntoIterator<Item = &'s str>,
+        query: impl IntoIterator<Item = (&'s str, &'s str)>,
+    ) -> Result<Url> {
+        let mut url = Url::parse(self.cfg.api_base_url().as_str())?;
+        url.path_segments_mut()
+            .map_err(|()| url::ParseError::RelativeUrlWithCannotBeABaseBase)?
+            .pop_if_empty()
+            .extend(segments);
+
+        let mut query = query.into_iter().peekable();
+        if query.peek().is_some() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        Ok(url)
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        self.send_paged(request).await.map(|(result, _)| result)
+    }
+
+    async fn send_paged<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<(T, Option<ResultInfo>)> {
+        let response = self.cfg.authorize_request(request).send().await?;
+        let status = response.status();
+        let bytes = response.bytes().await?;
+
+        let envelope = serde_json::from_slice::<Envelope<T>>(&bytes).map_err(|err| {
+            CloudflareError::InvalidResponse {
+                status,
+                err,
+                body: String::from_utf8_lossy(&bytes).into(),
+            }
+        })?;
+
+        if !status.is_success() || !envelope.success {
+            return Err(CloudflareError::Api {
+                status,
+                errors: ApiErrors(envelope.errors.into_boxed_slice()),
+            });
+        }
+
+        for message in &envelope.messages {
+            log::info!("cloudflare: {message}");
+        }
+
+        envelope
+            .result
+            .map(|result| (result, envelope.result_info))
+            .ok_or(CloudflareError::MissingResult)
+    }
+
+    /// walks every page of a list endpoint
+    async fn list_all<T: DeserializeOwned>(&self, url: Url) -> Result<Vec<T>> {
+        let mut items = vec![];
+        let mut page = 1_u32;
+
+        loop {
+            let mut url = url.clone();
+            url.query_pairs_mut().append_pair("page", &page.to_string());
+            let (result, info) = self.send_paged::<Vec<T>>(self.client.get(url)).await?;
+
+            // a page other than the one asked for means the api ignored `page`,
+            // it would just hand the same results back forever
+            if let Some(info) = info.as_ref().filter(|info| info.page != page) {
+                return Err(CloudflareError::UnexpectedPage {
+                    requested: page,
+                    received: info.page,
+                });
+            }
+
+            let empty = result.is_empty();
+            items.extend(result);
+
+            let more = match info {
+                Some(ResultInfo {
+                    total_pages: Some(total),
+                    ..
+                }) => page < total,
+                // without a page count keep going until we get an empty page
+                Some(ResultInfo {
+                    total_pages: None, ..
+                }) => !empty,
+                None => false,
+            };
+
+            if !more {
+                return Ok(items);
+            }
+            if page >= MAX_PAGES {
+                return Err(CloudflareError::TooManyPages(page));
+            }
+
+            page += 1;
+        }
+    }
+
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+        body: &impl Serialize,
+    ) -> Result<T> {
+        let body = serde_json::to_vec(body).map_err(CloudflareError::Serialize)?;
+        self.send(request.json(body)).await
+    }
+
+    pub async fn list_records(
+        &self,
+        zone_id: &str,
+        query: &RecordQuery<'_>,
+    ) -> Result<Vec<DnsRecord>> {
+        let per_page = query.per_page.unwrap_or(RECORDS_PER_PAGE).to_string();
+
+        let params = [
+            ("type", query.kind.map(RecordType::as_str)),
+            ("name", query.name),
+            ("content", query.content),
+            ("comment", query.comment),
+            ("comment.contains", query.comment_contains),
+            ("match", (query.r#match == Match::Any).then_some("any")),
+            ("per_page", Some(&*per_page)),
+        ];
+
+        let params = params
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v?)))
+            .chain(query.tags.iter().map(|tag| ("tag", *tag)));
+
+        self.list_all(self.url(["zones", zone_id, "dns_records"], params)?)
+            .await
+    }
+
+    pub async fn patch_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        patch: &PatchRecord<'_>,
+    ) -> Result<DnsRecord> {
+        let url = self.url(["zones", zone_id, "dns_records", record_id], [])?;
+        self.send_json(self.client.patch(url), patch).await
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// a single entry of the `errors` or `messages` arrays every response is wrapped in
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiMessage {
+    pub code: u32,
+    pub message: Box<str>,
+}
+
+impl Display for ApiMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "code {}: {}", self.code, self.message)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Envelope<T> {
+    pub success: bool,
+    #[serde(default)]
+    pub errors: Vec<ApiMessage>,
+    #[serde(default)]
+    pub messages: Vec<ApiMessage>,
+    pub result: Option<T>,
+    #[serde(default)]
+    pub result_info: Option<ResultInfo>,
+}
+
+/// pagination info of list endpoints
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ResultInfo {
+    pub page: u32,
+    #[serde(default)]
+    pub total_pages: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RecordType {
+    A,
+    AAAA,
+    CAA,
+    CNAME,
+    HTTPS,
+    MX,
+    NS,
+    PTR,
+    SRV,
+    SVCB,
+    TXT,
+    #[serde(other)]
+    #[serde(skip_serializing)]
+    Other,
+}
+
+impl RecordType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordType::A => "A",
+            RecordType::AAAA => "AAAA",
+            RecordType::CAA => "CAA",
+            RecordType::CNAME => "CNAME",
+            RecordType::HTTPS => "HTTPS",
+            RecordType::MX => "MX",
+            RecordType::NS => "NS",
+            RecordType::PTR => "PTR",
+            RecordType::SRV => "SRV",
+            RecordType::SVCB => "SVCB",
+            RecordType::TXT => "TXT",
+            RecordType::Other => "OTHER",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DnsRecord {
+    pub id: Box<str>,
+    pub name: Box<str>,
+    pub content: Box<str>,
+}
+
+/// body of `PATCH zones/:zone_id/dns_records/:id`, fields left as `None` are not touched
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PatchRecord<'a> {
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<RecordType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<&'a [&'a str]>,
+}
+
+/// how multiple filters of a [`RecordQuery`] are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Match {
+    #[default]
+    All,
+    Any,
+}
+
+/// query of `GET zones/:zone_id/dns_records`, all the filtering is done by cloudflare
+#[derive(Debug, Clone, Default)]
+pub struct RecordQuery<'a> {
+    pub kind: Option<RecordType>,
+    pub name: Option<&'a str>,
+    pub content: Option<&'a str>,
+    /// `name` to require a tag to be present, or `name:value` to require it to have that value
+    pub tags: &'a [&'a str],
+    pub comment: Option<&'a str>,
+    pub comment_contains: Option<&'a str>,
+    pub r#match: Match,
+    /// defaults to `RECORDS_PER_PAGE`, 1000 rather than the 100 cloudflare uses
+    pub per_page: Option<u32>,
+}
@@ -3,6 +3,7 @@ use anyhow::Result;
 use reqwest::header::HeaderValue;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer};
+use url::Url;
 
 #[derive(Eq, Ord, PartialOrd, PartialEq, Debug)]
 pub(super) enum Auth {
@@ -41,7 +42,7 @@ impl<'de> Deserialize<'de> for Account {
 
         let auth = match (inner.auth_token, inner.auth_key) {
             (Some(token), None) => Auth::Token(
-                HeaderValue::from_str(&("Bearer ".to_owned() + &token))
+                HeaderValue::from_str(&("Bearer ".to_owned() + &*token))
                     .map_err(|_| invalid_header!("auth-token"))?,
             ),
             (None, Some(key)) => {
@@ -107,8 +108,53 @@ impl Zone {
     }
 }
 
+/// base url of the Cloudflare v4 API without a trailing slash
+#[derive(Eq, Ord, PartialOrd, PartialEq, Debug)]
+pub struct ApiBaseUrl(Url);
+
+impl ApiBaseUrl {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str().trim_end_matches('/')
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.0.host_str()
+    }
+}
+
+impl Default for ApiBaseUrl {
+    fn default() -> Self {
+        ApiBaseUrl(Url::parse("https://api.cloudflare.com/client/v4").unwrap())
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiBaseUrl {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let url = String::deserialize(deserializer)?;
+        let url = Url::parse(&url).map_err(|e| Error::custom(format_args!("api-base-url {e}")))?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::custom("api-base-url must be an http(s) url"));
+        }
+
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err(Error::custom(
+                "api-base-url can't have a query or a fragment",
+            ));
+        }
+
+        Ok(ApiBaseUrl(url))
+    }
+}
+
 #[derive(Eq, Ord, PartialOrd, PartialEq, Deserialize, Debug)]
 pub struct ApiFields {
+    #[serde(default)]
+    #[serde(alias = "api-base-url")]
+    pub(crate) api_base_url: ApiBaseUrl,
     pub(crate) account: Account,
     pub(crate) zone: Zone,
 }
@@ -0,0 +1,211 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::fmt::{Debug, Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+/// an address block like `100.64.0.0/10` or `2001:db8::/32`, a bare address is a block of one
+#[derive(Copy, Clone, PartialOrd, PartialEq, Ord, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Cidr {
+    pub const fn v4(a: u8, b: u8, c: u8, d: u8, len: u8) -> Self {
+        Cidr {
+            addr: IpAddr::V4(Ipv4Addr::new(a, b, c, d)),
+            len,
+        }
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    fn mask(bits: u32, len: u8) -> u128 {
+        match len {
+            0 => 0,
+            len => u128::MAX << (bits - len as u32),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = Self::mask(32, self.len);
+                u32::from(net) as u128 & mask == u32::from(ip) as u128 & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = Self::mask(128, self.len);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// ranges that never belong to a host on the public internet,
+/// ipv4 only on purpose as we only ever publish A records
+pub const BOGONS: [(Cidr, &str); 14] = [
+    (Cidr::v4(0, 0, 0, 0, 8), "this network"),
+    (Cidr::v4(10, 0, 0, 0, 8), "private"),
+    (Cidr::v4(100, 64, 0, 0, 10), "shared address space (cgnat)"),
+    (Cidr::v4(127, 0, 0, 0, 8), "loopback"),
+    (Cidr::v4(169, 254, 0, 0, 16), "link local"),
+    (Cidr::v4(172, 16, 0, 0, 12), "private"),
+    (Cidr::v4(192, 0, 0, 0, 24), "protocol assignments"),
+    (Cidr::v4(192, 0, 2, 0, 24), "documentation"),
+    (Cidr::v4(192, 168, 0, 0, 16), "private"),
+    (Cidr::v4(198, 18, 0, 0, 15), "benchmarking"),
+    (Cidr::v4(198, 51, 100, 0, 24), "documentation"),
+    (Cidr::v4(203, 0, 113, 0, 24), "documentation"),
+    (Cidr::v4(224, 0, 0, 0, 4), "multicast"),
+    (Cidr::v4(240, 0, 0, 0, 4), "reserved"),
+];
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+
+        let addr = IpAddr::from_str(addr.trim()).map_err(|e| format!("{s:?}: {e}"))?;
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let len = match len {
+            Some(len) => len
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max)
+                .ok_or_else(|| format!("{s:?}: the prefix length has to be at most {max}"))?,
+            None => max,
+        };
+
+        Ok(Cidr { addr, len })
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+impl Debug for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        <Self as Display>::fmt(self, f)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        Cidr::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_blocks() {
+        assert_eq!(cidr("100.64.0.0/10"), Cidr::v4(100, 64, 0, 0, 10));
+        assert_eq!(cidr(" 192.0.2.1 / 24 "), Cidr::v4(192, 0, 2, 1, 24));
+        assert_eq!(cidr("192.0.2.1"), Cidr::v4(192, 0, 2, 1, 32));
+        assert_eq!(cidr("2001:db8::").to_string(), "2001:db8::/128");
+        assert_eq!(format!("{:?}", cidr("2001:db8::/32")), "2001:db8::/32");
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        for (block, err) in [
+            ("192.0.2.0/33", "the prefix length has to be at most 32"),
+            ("2001:db8::/129", "the prefix length has to be at most 128"),
+            ("192.0.2.0/-1", "the prefix length has to be at most 32"),
+            ("192.0.2.0/", "the prefix length has to be at most 32"),
+            ("example.com/8", "invalid IP address syntax"),
+        ] {
+            let e = block.parse::<Cidr>().unwrap_err();
+            assert!(e.contains(err), "{block}: {e}");
+        }
+    }
+
+    #[test]
+    fn matches_addresses() {
+        let cgnat = cidr("100.64.0.0/10");
+        assert!(cgnat.contains(ip("100.64.0.0")));
+        assert!(cgnat.contains(ip("100.127.255.255")));
+        assert!(!cgnat.contains(ip("100.128.0.0")));
+        assert!(!cgnat.contains(ip("100.63.255.255")));
+
+        // the host bits of the block don't matter
+        assert!(cidr("192.0.2.77/24").contains(ip("192.0.2.1")));
+        assert!(cidr("192.0.2.1").contains(ip("192.0.2.1")));
+        assert!(!cidr("192.0.2.1").contains(ip("192.0.2.2")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.7")));
+
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(!cidr("2001:db8::/32").contains(ip("2001:db9::1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn keeps_families_apart() {
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+        assert!(!cidr("::/0").contains(ip("127.0.0.1")));
+        assert!(!cidr("::ffff:0:0/96").contains(ip("192.0.2.1")));
+        assert!(cidr("192.0.2.0/24").is_ipv4());
+        assert!(!cidr("2001:db8::/32").is_ipv4());
+    }
+
+    #[test]
+    fn lists_bogons() {
+        let bogon = |addr: &str| BOGONS.iter().find(|(cidr, _)| cidr.contains(ip(addr)));
+        assert_eq!(bogon("10.1.2.3").unwrap().1, "private");
+        assert_eq!(
+            bogon("100.100.0.1").unwrap().1,
+            "shared address space (cgnat)"
+        );
+        assert_eq!(bogon("255.255.255.255").unwrap().1, "reserved");
+        assert!(bogon("1.1.1.1").is_none());
+        assert!(bogon("100.128.0.1").is_none());
+        assert!(BOGONS.iter().all(|(cidr, _)| cidr.is_ipv4()));
+    }
+
+    #[test]
+    fn deserializes_from_strings() {
+        #[derive(Debug, Deserialize)]
+        struct Blocks {
+            allow: Vec<Cidr>,
+        }
+
+        let blocks: Blocks = toml::from_str(r#"allow = ["192.0.2.0/24", "198.51.100.1"]"#).unwrap();
+        assert_eq!(
+            blocks.allow,
+            [Cidr::v4(192, 0, 2, 0, 24), Cidr::v4(198, 51, 100, 1, 32)]
+        );
+
+        let err = toml::from_str::<Blocks>(r#"allow = ["192.0.2.0/40"]"#).unwrap_err();
+        assert!(err.to_string().contains("at most 32"), "{err}");
+    }
+}
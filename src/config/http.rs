@@ -1,7 +1,11 @@
 use crate::config::time::Time;
 use crate::config::Deserializable;
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 #[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
@@ -60,19 +64,102 @@ impl Default for ClientConfig {
     }
 }
 
+/// sha256 hash of a DER encoded SubjectPublicKeyInfo, written as `sha256/<base64>`
+#[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Copy, Clone)]
+pub struct SpkiPin(pub [u8; 32]);
+
+impl<'de> Deserialize<'de> for SpkiPin {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pin = String::deserialize(deserializer)?;
+        let hash = pin
+            .strip_prefix("sha256/")
+            .ok_or_else(|| Error::custom("pins must be in the format of 'sha256/<base64>'"))?;
+
+        BASE64_STANDARD
+            .decode(hash)
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .map(SpkiPin)
+            .ok_or_else(|| Error::custom(format_args!("invalid sha256 pin `{pin}`")))
+    }
+}
+
+#[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize, Default)]
+pub struct TlsConfig {
+    #[serde(default)]
+    #[serde(alias = "ca-file")]
+    ca_file: Option<PathBuf>,
+    /// DER encoded certificates loaded from `ca_file`
+    #[serde(skip)]
+    ca_certs: Box<[Box<[u8]>]>,
+    /// matched against every key on the verified chain, users should pin an intermediate or ca
+    /// rather than the leaf, which cloudflare rotates
+    #[serde(default)]
+    #[serde(alias = "cloudflare-pins")]
+    cloudflare_pins: Box<[SpkiPin]>,
+}
+
+impl TlsConfig {
+    async fn load_ca_file(path: &Path) -> Result<Box<[Box<[u8]>]>> {
+        let pem = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("unable to read ca-file {}", path.display()))?;
+
+        let certs = rustls_pemfile::certs(&mut BufReader::new(&*pem))
+            .map(|cert| cert.map(|cert| Box::from(&*cert)))
+            .collect::<std::io::Result<Box<[_]>>>()
+            .with_context(|| format!("invalid pem in ca-file {}", path.display()))?;
+
+        anyhow::ensure!(
+            !certs.is_empty(),
+            "ca-file {} contains no certificates",
+            path.display()
+        );
+
+        Ok(certs)
+    }
+
+    /// extra trust anchors on top of the native root store
+    pub fn ca_certs(&self) -> &[Box<[u8]>] {
+        &self.ca_certs
+    }
+
+    pub fn cloudflare_pins(&self) -> &[SpkiPin] {
+        &self.cloudflare_pins
+    }
+
+    /// whether we have to build the tls config ourselves
+    pub fn is_custom(&self) -> bool {
+        !self.ca_certs.is_empty() || !self.cloudflare_pins.is_empty()
+    }
+}
+
 #[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
 pub struct HttpConfig {
     client: ClientConfig,
+    #[serde(default)]
+    tls: TlsConfig,
 }
 
 impl HttpConfig {
     pub fn client(&self) -> &ClientConfig {
         &self.client
     }
+
+    pub fn tls(&self) -> &TlsConfig {
+        &self.tls
+    }
 }
 
 impl Deserializable for HttpConfig {
     async fn deserialize(text: &str) -> Result<Self> {
-        Ok(toml::de::from_str(text)?)
+        let mut cfg = toml::de::from_str::<HttpConfig>(text)?;
+        if let Some(path) = &cfg.tls.ca_file {
+            cfg.tls.ca_certs = TlsConfig::load_ca_file(path).await?;
+        }
+        Ok(cfg)
     }
 }
@@ -1,20 +1,23 @@
+use crate::config::json_path::JsonPath;
+use crate::config::misc::RejectedAddress;
+use crate::config::script::Script;
 use crate::config::{Config, Deserializable};
 use crate::retrying_client::RetryingClient;
+use crate::sources::{Fetched, SourceKind};
 use crate::util::{num_cpus, AddrParseError, AddrParseExt};
 use crate::{abort_unreachable, non_zero};
 use anyhow::Result;
 use bytes::Bytes;
 use futures::task::noop_waker_ref;
 use futures::{StreamExt, TryStreamExt};
-use serde::de::{Error, MapAccess, SeqAccess, Visitor};
+use reqwest::header::HeaderName;
+use serde::de::{Error, SeqAccess, Visitor};
 use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_json::de::SliceRead;
-use serde_json::Deserializer as JsonDeserializer;
 use simdutf8::basic::Utf8Error;
 use std::collections::BTreeMap;
 use std::convert::Infallible;
-use std::fmt::{Debug, Formatter, Write};
+use std::fmt::{Debug, Display, Formatter, Write};
 use std::future::Future;
 use std::net::Ipv4Addr;
 use std::num::NonZeroU8;
@@ -39,9 +42,150 @@ pub enum GetIpError {
     InvalidIp(#[from] AddrParseError),
     #[error("There is no ip source to get our ip from")]
     NoIpSources,
+    #[error("none of the ip sources answered")]
+    NoAnswer,
+    #[error("{0}")]
+    NoQuorum(NoQuorum),
+    #[error("the pattern `{0}` didn't match")]
+    NoMatch(Box<str>),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("invalid dns message: {0}")]
+    DnsProto(#[from] hickory_proto::error::ProtoError),
+    #[error("{0}")]
+    Dns(Box<str>),
+    #[error("{0}")]
+    Stun(Box<str>),
+    #[error("{0}")]
+    Gateway(Box<str>),
+    #[error("{0}")]
+    Interface(Box<str>),
+    #[error("{0}")]
+    Exec(Box<str>),
+    #[error("{0}")]
+    File(Box<str>),
+    #[error(transparent)]
+    Rejected(#[from] RejectedAddress),
+    #[error("steps[{index}] ({step}) failed: {err}")]
+    Step {
+        index: usize,
+        step: &'static str,
+        err: Box<GetIpError>,
+    },
+    #[error("{0}")]
+    Script(Box<str>),
+    #[error("the response is larger than the {limit} byte limit")]
+    BodyTooLarge { limit: u64 },
+    #[error("the response has no `{0}` header")]
+    MissingHeader(Box<str>),
+    #[error("the source answered with {0}")]
+    Status(reqwest::StatusCode),
+    #[error("there are less than {} parts separated by {delimiter:?}", index + 1)]
+    NoSuchPart { delimiter: StrOrBytes, index: usize },
 }
 
-#[derive(PartialOrd, PartialEq, Ord, Eq)]
+/// opt-in consensus between sources, so a single bad source can't make us publish a wrong address
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Ord, Eq)]
+pub struct Quorum {
+    /// how many sources have to answer
+    pub query: NonZeroU8,
+    /// how many of the answers have to be the same address
+    pub agree: NonZeroU8,
+}
+
+impl<'de> Deserialize<'de> for Quorum {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct QuorumInner {
+            query: NonZeroU8,
+            agree: NonZeroU8,
+        }
+
+        let QuorumInner { query, agree } = QuorumInner::deserialize(deserializer)?;
+        if agree > query {
+            return Err(Error::custom(format_args!(
+                "quorum can't require {agree} sources to agree when only {query} are queried"
+            )));
+        }
+
+        Ok(Quorum { query, agree })
+    }
+}
+
+#[derive(Debug)]
+pub struct NoQuorum {
+    agree: NonZeroU8,
+    /// more than one address got the most votes
+    tie: bool,
+    answers: Vec<(Url, Ipv4Addr)>,
+}
+
+impl Display for NoQuorum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.tie {
+            true => write!(
+                f,
+                "no quorum, several addresses got the same number of votes out of {} answers:",
+                self.answers.len()
+            )?,
+            false => write!(
+                f,
+                "no quorum, needed {} sources to agree but got {} answers:",
+                self.agree,
+                self.answers.len()
+            )?,
+        }
+        for (url, ip) in &self.answers {
+            write!(f, "\n{url} => {ip}")?;
+        }
+        Ok(())
+    }
+}
+
+/// the agreed upon address, and the sources that disagreed with it
+pub type Consensus = (Ipv4Addr, Vec<(Url, Ipv4Addr)>);
+
+impl Quorum {
+    pub fn decide(&self, answers: Vec<(Url, Ipv4Addr)>) -> Result<Consensus, GetIpError> {
+        let mut votes = BTreeMap::<Ipv4Addr, u8>::new();
+        for (_, ip) in &answers {
+            *votes.entry(*ip).or_default() += 1;
+        }
+
+        // a tie can't be broken without picking an address at random
+        let most = votes.values().copied().max().unwrap_or(0);
+        let leaders = votes
+            .into_iter()
+            .filter(|&(_, votes)| votes == most)
+            .map(|(ip, _)| ip)
+            .collect::<Vec<_>>();
+        let tie = leaders.len() > 1;
+        let winner = match *leaders {
+            [ip] if most >= self.agree.get() => Some(ip),
+            _ => None,
+        };
+
+        match winner {
+            Some(ip) => Ok((
+                ip,
+                answers
+                    .into_iter()
+                    .filter(|(_, other)| *other != ip)
+                    .collect(),
+            )),
+            None => Err(GetIpError::NoQuorum(NoQuorum {
+                agree: self.agree,
+                tie,
+                answers,
+            })),
+        }
+    }
+}
+
+#[derive(Clone, PartialOrd, PartialEq, Ord, Eq)]
 pub struct StrOrBytes(pub Box<[u8]>);
 
 impl<'de> Deserialize<'de> for StrOrBytes {
@@ -129,6 +273,90 @@ impl Deref for StrOrBytes {
     }
 }
 
+/// a regex that is compiled as soon as `sources.toml` is loaded
+#[derive(Clone)]
+pub struct Pattern(regex::bytes::Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        regex::bytes::Regex::new(pattern).map(Pattern)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl Deref for Pattern {
+    type Target = regex::bytes::Regex;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl PartialOrd for Pattern {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pattern {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Debug for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        <str as Debug>::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        Pattern::new(&pattern).map_err(Error::custom)
+    }
+}
+
+/// a capture group, either by its index or by its name
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CaptureGroup {
+    Index(usize),
+    Name(Box<str>),
+}
+
+impl Display for CaptureGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureGroup::Index(i) => write!(f, "{i}"),
+            CaptureGroup::Name(name) => write!(f, "`{name}`"),
+        }
+    }
+}
+
 #[derive(Debug, PartialOrd, PartialEq, Ord, Eq, Serialize, Deserialize)]
 pub enum ProcessStep {
     /// parses the current data as utf-8
@@ -141,37 +369,56 @@ pub enum ProcessStep {
     },
 
     /// parses the current data as a json, and extracts the value from
-    Json { key: Box<str> },
-}
+    /// a top level key, a json pointer (`/data/ip`) or a dotted path (`$.data.ips[0]`)
+    Json { key: JsonPath },
 
-fn get_json_key(json: &[u8], key: &str) -> serde_json::Result<serde_json::Value> {
-    let mut deserializer = JsonDeserializer::new(SliceRead::new(json));
+    /// narrows the current data to a capture group of the first match,
+    /// defaults to the first group, or the whole match if there are no groups
+    Regex {
+        pattern: Pattern,
+        group: Option<CaptureGroup>,
+    },
 
-    struct JsonVisitor<'a> {
-        key: &'a str,
-    }
+    /// removes leading and trailing ascii whitespace, including `\r` and `\n`
+    Trim,
 
-    impl<'de, 'a> Visitor<'de> for JsonVisitor<'a> {
-        type Value = serde_json::Value;
+    /// keeps a single line, without its `\n` or `\r\n` line ending
+    Line { index: usize },
 
-        fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
-            write!(formatter, "a json with a field {}", self.key)
-        }
+    /// splits the current data on a delimiter and keeps a single part
+    Split { delimiter: StrOrBytes, index: usize },
 
-        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
-            let mut val = None;
+    /// replaces the current data with a response header, multiple values are joined with `, `,
+    /// `location` falls back to where the last followed redirect pointed to
+    Header { name: Box<str> },
 
-            while let Some((key, value)) = map.next_entry::<&str, serde_json::Value>()? {
-                if val.is_none() && key == self.key {
-                    val = Some(value);
-                }
-            }
+    /// fails unless the response status is one of `allow`, or any 2xx status if it's empty,
+    /// sources without a status always pass
+    Status {
+        #[serde(default)]
+        allow: Box<[u16]>,
+    },
 
-            val.ok_or_else(|| Error::custom(format_args!("missing field `{}`", self.key)))
-        }
+    /// runs a rhai script on the current data, for extraction logic the other steps can't express
+    Script { code: Script },
+}
+
+/// the `index`th part of `bytes` when split on `delimiter`
+fn nth_part(bytes: &Bytes, delimiter: &[u8], index: usize) -> Option<Bytes> {
+    let mut start = 0;
+    for _ in 0..index {
+        let found = bytes[start..]
+            .windows(delimiter.len())
+            .position(|window| window == delimiter)?;
+        start += found + delimiter.len();
     }
 
-    deserializer.deserialize_map(JsonVisitor { key })
+    let end = bytes[start..]
+        .windows(delimiter.len())
+        .position(|window| window == delimiter)
+        .map_or(bytes.len(), |found| start + found);
+
+    Some(bytes.slice(start..end))
 }
 
 #[derive(Clone, Debug, PartialOrd, PartialEq, Ord, Eq, Serialize, Deserialize)]
@@ -179,73 +426,277 @@ struct Process {
     steps: Arc<[ProcessStep]>,
 }
 
-impl Process {
-    async fn run(&self, mut bytes: Bytes, _cfg: &Config) -> Result<Ipv4Addr, GetIpError> {
+impl ProcessStep {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessStep::Plaintext => "Plaintext",
+            ProcessStep::Strip { .. } => "Strip",
+            ProcessStep::Json { .. } => "Json",
+            ProcessStep::Regex { .. } => "Regex",
+            ProcessStep::Trim => "Trim",
+            ProcessStep::Line { .. } => "Line",
+            ProcessStep::Split { .. } => "Split",
+            ProcessStep::Header { .. } => "Header",
+            ProcessStep::Status { .. } => "Status",
+            ProcessStep::Script { .. } => "Script",
+        }
+    }
+
+    async fn apply(&self, mut bytes: Bytes, fetched: &Fetched) -> Result<Bytes, GetIpError> {
         use ProcessStep as S;
-        for step in &*self.steps {
-            match step {
-                S::Plaintext => {
-                    simdutf8::basic::from_utf8(&bytes)?;
+        match self {
+            S::Plaintext => {
+                simdutf8::basic::from_utf8(&bytes)?;
+            }
+            S::Strip { prefix, suffix } => {
+                if let Some(prefix) = prefix {
+                    if bytes.starts_with(prefix) {
+                        bytes = bytes.split_off(prefix.len());
+                    }
                 }
-                S::Strip { prefix, suffix } => {
-                    if let Some(prefix) = prefix {
-                        if bytes.starts_with(prefix) {
-                            bytes = bytes.split_off(prefix.len());
-                        }
+
+                if let Some(suffix) = suffix {
+                    if bytes.ends_with(suffix) {
+                        bytes.truncate(bytes.len() - suffix.len())
                     }
+                }
+            }
+            S::Json { key } => {
+                let val = match key.extract(&bytes)? {
+                    serde_json::Value::String(str) => str,
+                    val => format!("{val}"),
+                };
+                bytes = val.into()
+            }
+            S::Regex { pattern, group } => {
+                let captures = pattern
+                    .captures(&bytes)
+                    .ok_or_else(|| GetIpError::NoMatch(pattern.as_str().into()))?;
 
-                    if let Some(suffix) = suffix {
-                        if bytes.ends_with(suffix) {
-                            bytes.truncate(bytes.len() - suffix.len())
+                let capture = match group {
+                    Some(CaptureGroup::Index(i)) => captures.get(*i),
+                    Some(CaptureGroup::Name(name)) => captures.name(name),
+                    None => captures.get(1).or_else(|| captures.get(0)),
+                };
+
+                // a group can be optional, and not take part in the match
+                let range = capture
+                    .ok_or_else(|| GetIpError::NoMatch(pattern.as_str().into()))?
+                    .range();
+                bytes = bytes.slice(range);
+            }
+            S::Trim => {
+                let start = bytes
+                    .iter()
+                    .position(|b| !b.is_ascii_whitespace())
+                    .unwrap_or(bytes.len());
+                let end = bytes
+                    .iter()
+                    .rposition(|b| !b.is_ascii_whitespace())
+                    .map_or(start, |end| end + 1);
+                bytes = bytes.slice(start..end);
+            }
+            S::Line { index } => {
+                bytes = nth_part(&bytes, b"\n", *index).ok_or_else(|| GetIpError::NoSuchPart {
+                    delimiter: StrOrBytes(Box::from(&b"\n"[..])),
+                    index: *index,
+                })?;
+                if bytes.ends_with(b"\r") {
+                    bytes.truncate(bytes.len() - 1)
+                }
+            }
+            S::Split { delimiter, index } => {
+                bytes =
+                    nth_part(&bytes, delimiter, *index).ok_or_else(|| GetIpError::NoSuchPart {
+                        delimiter: delimiter.clone(),
+                        index: *index,
+                    })?;
+            }
+            S::Header { name } => {
+                let mut values = fetched.headers.get_all(&**name).iter();
+                bytes = match values.next() {
+                    Some(first) => {
+                        let mut value = first.as_bytes().to_vec();
+                        for next in values {
+                            value.extend_from_slice(b", ");
+                            value.extend_from_slice(next.as_bytes());
                         }
+                        value.into()
                     }
-                }
-                S::Json { key } => {
-                    let val = match get_json_key(&bytes, key)? {
-                        serde_json::Value::String(str) => str,
-                        val => format!("{val}"),
-                    };
-                    bytes = val.into()
+                    None if name.eq_ignore_ascii_case("location") => fetched
+                        .redirected_to
+                        .as_ref()
+                        .map(|url| Bytes::from(url.to_string()))
+                        .ok_or_else(|| GetIpError::MissingHeader(name.clone()))?,
+                    None => return Err(GetIpError::MissingHeader(name.clone())),
+                };
+            }
+            S::Status { allow } => {
+                let Some(status) = fetched.status else {
+                    return Ok(bytes);
+                };
+
+                let allowed = match allow.is_empty() {
+                    true => status.is_success(),
+                    false => allow.contains(&status.as_u16()),
+                };
+                if !allowed {
+                    return Err(GetIpError::Status(status));
                 }
             }
+            S::Script { code } => bytes = code.run(&bytes, fetched).await?,
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl Process {
+    async fn run(&self, fetched: Fetched, _cfg: &Config) -> Result<Ipv4Addr, GetIpError> {
+        let mut bytes = fetched.body.clone();
+        for (index, step) in self.steps.iter().enumerate() {
+            bytes = step
+                .apply(bytes, &fetched)
+                .await
+                .map_err(|err| GetIpError::Step {
+                    index,
+                    step: step.name(),
+                    err: Box::new(err),
+                })?;
         }
 
         Ok(Ipv4Addr::parse_ascii_bytes(&bytes)?)
     }
 }
 
-async fn into_process(mut steps: Vec<ProcessStep>) -> Process {
+async fn into_process(mut steps: Vec<ProcessStep>) -> Result<Process> {
     while let Some(ProcessStep::Plaintext) = steps.last() {
         steps.pop();
     }
 
-    steps.dedup_by(|x, y| matches!((x, y), (ProcessStep::Plaintext, ProcessStep::Plaintext)));
+    steps.dedup_by(|x, y| {
+        matches!(
+            (x, y),
+            (ProcessStep::Plaintext, ProcessStep::Plaintext)
+                | (ProcessStep::Trim, ProcessStep::Trim)
+        )
+    });
 
     let steps = futures::stream::iter(steps)
         .map(|step| async move {
             use ProcessStep as S;
             match step {
-                step @ (S::Json { .. } | S::Plaintext) => Some(step),
+                step @ (S::Json { .. }
+                | S::Plaintext
+                | S::Trim
+                | S::Line { .. }
+                | S::Script { .. }) => Ok(Some(step)),
+                S::Split { delimiter, .. } if delimiter.is_empty() => {
+                    anyhow::bail!("can't split on an empty delimiter")
+                }
+                step @ S::Split { .. } => Ok(Some(step)),
+                S::Header { name } => match HeaderName::from_bytes(name.as_bytes()) {
+                    Ok(_) => Ok(Some(S::Header { name })),
+                    Err(_) => anyhow::bail!("`{name}` isn't a valid header name"),
+                },
+                S::Status { allow } => match allow.iter().find(|x| !(100..1000).contains(*x)) {
+                    Some(code) => anyhow::bail!("{code} isn't a valid status code"),
+                    None => Ok(Some(S::Status { allow })),
+                },
                 S::Strip { prefix, suffix } => match (prefix, suffix) {
-                    (None, None) => None,
-                    (prefix, suffix) => Some(S::Strip { prefix, suffix }),
+                    (None, None) => Ok(None),
+                    (prefix, suffix) => Ok(Some(S::Strip { prefix, suffix })),
                 },
+                S::Regex { pattern, group } => {
+                    let exists = match &group {
+                        None => true,
+                        Some(CaptureGroup::Index(i)) => *i < pattern.captures_len(),
+                        Some(CaptureGroup::Name(name)) => {
+                            pattern.capture_names().flatten().any(|x| x == &**name)
+                        }
+                    };
+
+                    if let (false, Some(group)) = (exists, &group) {
+                        anyhow::bail!(
+                            "the pattern `{}` has no capture group {group}",
+                            pattern.as_str()
+                        )
+                    }
+
+                    Ok(Some(S::Regex { pattern, group }))
+                }
             }
         })
         .buffered(num_cpus().get())
-        .filter_map(|x| async move { x })
-        .collect::<Vec<_>>()
-        .await;
+        .try_filter_map(|x| async move { Ok(x) })
+        .try_collect::<Vec<_>>()
+        .await?;
 
-    Process {
+    Ok(Process {
         steps: steps.into(),
+    })
+}
+
+/// a source as found in `sources.toml`, the kind is derived from its url unless it's a local source,
+/// local and http sources are also compared on their options so reloads pick up changes
+#[derive(Clone)]
+struct Source {
+    kind: Arc<SourceKind>,
+    process: Process,
+    tier: Option<u8>,
+}
+
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Source {}
+
+impl PartialOrd for Source {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Source {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (
+            &self.process,
+            self.tier,
+            self.kind.local(),
+            self.kind.http(),
+        )
+            .cmp(&(
+                &other.process,
+                other.tier,
+                other.kind.local(),
+                other.kind.http(),
+            ))
+    }
+}
+
+impl Debug for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.process.fmt(f)
     }
 }
 
+/// how a tier of sources is resolved, set under `[tiers.<n>]`
+#[derive(Debug, Default, Copy, Clone, PartialOrd, PartialEq, Ord, Eq, Deserialize)]
+pub struct TierConfig {
+    /// defaults to the top level `concurrent-resolve`
+    #[serde(default, alias = "concurrent-resolve")]
+    concurrent_resolve: Option<NonZeroU8>,
+}
+
 #[derive(PartialOrd, PartialEq, Ord, Eq)]
 pub struct Sources {
-    sources: BTreeMap<Url, Process>,
+    sources: BTreeMap<Url, Source>,
     pub(crate) concurrent_resolve: NonZeroU8,
+    pub(crate) quorum: Option<Quorum>,
+    tiers: BTreeMap<u8, TierConfig>,
 }
 
 impl Sources {
@@ -254,24 +705,33 @@ impl Sources {
         concurrent_resolve: Option<NonZeroU8>,
     ) -> Result<Self>
     where
-        I: IntoIterator<Item = Result<(Url, Steps), E>>,
+        I: IntoIterator<Item = Result<(Url, Option<SourceKind>, Option<u8>, Steps), E>>,
         E: Into<anyhow::Error>,
         Url: AsRef<str>,
         Steps: IntoIterator<Item = ProcessStep>,
     {
         futures::stream::iter(iter)
             .map(|res| async move {
-                let (url, steps) = res.map_err(Into::into)?;
-                Ok((
-                    url::Url::parse(url.as_ref())?,
-                    into_process(steps.into_iter().collect()).await,
-                ))
+                let (url, kind, tier, steps) = res.map_err(Into::into)?;
+                let url = url::Url::parse(url.as_ref())?;
+                let kind = match kind {
+                    Some(kind) => kind,
+                    None => SourceKind::parse(&url)?,
+                };
+                let source = Source {
+                    kind: Arc::new(kind),
+                    process: into_process(steps.into_iter().collect()).await?,
+                    tier,
+                };
+                anyhow::Ok((url, source))
             })
             .buffer_unordered(num_cpus().get())
-            .try_collect::<BTreeMap<url::Url, Process>>()
+            .try_collect::<BTreeMap<url::Url, Source>>()
             .await
             .map(|sources| Sources {
                 sources,
+                quorum: None,
+                tiers: BTreeMap::new(),
                 // # Safety:
                 // 16 is not = to 0, lol
                 concurrent_resolve: concurrent_resolve.unwrap_or_else(|| {
@@ -294,17 +754,43 @@ impl Sources {
         Steps: IntoIterator<Item = ProcessStep>,
     {
         Self::from_try_iter(
-            iter.into_iter().map(Ok::<_, Infallible>),
+            iter.into_iter()
+                .map(|(url, steps)| Ok::<_, Infallible>((url, None, None, steps))),
             concurrent_resolve,
         )
         .await
     }
 
-    pub fn sources(&self) -> impl Iterator<Item = IpSource> + '_ {
-        self.sources
-            .iter()
-            .map(|(url, process)| (url.clone(), process.clone()))
-            .map(|(url, process)| IpSource { url, process })
+    /// sources grouped by tier, lower tiers first and sources without one last,
+    /// with how many of them are resolved at once
+    pub fn tiers(&self) -> Vec<(NonZeroU8, Vec<IpSource>)> {
+        let mut tiers = BTreeMap::<Option<u8>, Vec<IpSource>>::new();
+        for (url, source) in &self.sources {
+            let Source {
+                kind,
+                process,
+                tier,
+            } = source.clone();
+            tiers.entry(tier).or_default().push(IpSource {
+                url: url.clone(),
+                kind,
+                process,
+            });
+        }
+
+        // `None` sorts first, but untiered sources are the last resort
+        let untiered = tiers.remove(&None);
+        tiers
+            .into_iter()
+            .map(|(tier, sources)| {
+                let concurrent = tier
+                    .and_then(|tier| self.tiers.get(&tier))
+                    .and_then(|cfg| cfg.concurrent_resolve)
+                    .unwrap_or(self.concurrent_resolve);
+                (concurrent, sources)
+            })
+            .chain(untiered.map(|sources| (self.concurrent_resolve, sources)))
+            .collect()
     }
 }
 
@@ -312,7 +798,10 @@ impl Deserializable for Sources {
     async fn deserialize(text: &str) -> Result<Self> {
         #[derive(Deserialize)]
         struct ProcessIntermediate {
+            #[serde(default)]
             steps: Vec<ProcessStep>,
+            #[serde(default)]
+            tier: Option<u8>,
         }
 
         let mut value = toml::from_str::<Map<String, Value>>(text)?;
@@ -336,13 +825,47 @@ impl Deserializable for Sources {
                 NonZeroU8::new(val.try_into::<u8>()?).ok_or_else(|| anyhow::anyhow!("{key} can't be zero"))?
         );
 
-        Self::from_try_iter(
-            value
+        get_field!(
+            quorum: ["quorum"] => |_key, val| val.try_into::<Quorum>()?
+        );
+
+        get_field!(
+            tiers: ["tiers"] => |_key, val| val
+                .try_into::<BTreeMap<String, TierConfig>>()?
                 .into_iter()
-                .map(|(url, v)| v.try_into::<ProcessIntermediate>().map(|v| (url, v.steps))),
+                .map(|(tier, cfg)| match tier.parse::<u8>() {
+                    Ok(tier) => Ok((tier, cfg)),
+                    Err(_) => Err(anyhow::anyhow!("[tiers.{tier}] has to be a number from 0 to 255")),
+                })
+                .collect::<Result<BTreeMap<_, _>>>()?
+        );
+
+        let mut sources = Self::from_try_iter(
+            value.into_iter().map(|(key, v)| {
+                let ProcessIntermediate { steps, tier } = v.clone().try_into()?;
+                match SourceKind::from_table(&key, &v)? {
+                    Some((url, kind)) => anyhow::Ok((url.to_string(), Some(kind), tier, steps)),
+                    None => {
+                        let kind = SourceKind::parse_table(&url::Url::parse(&key)?, &v)?;
+                        anyhow::Ok((key, Some(kind), tier, steps))
+                    }
+                }
+            }),
             concurrent_resolve,
         )
-        .await
+        .await?;
+
+        let tiers = tiers.unwrap_or_default();
+        if let Some(tier) = tiers
+            .keys()
+            .find(|tier| !sources.sources.values().any(|x| x.tier == Some(**tier)))
+        {
+            anyhow::bail!("[tiers.{tier}] is configured, but no source has `tier = {tier}`")
+        }
+
+        sources.quorum = quorum;
+        sources.tiers = tiers;
+        Ok(sources)
     }
 }
 
@@ -351,6 +874,8 @@ impl Debug for Sources {
         f.debug_map()
             .entries(self.sources.iter().map(|(url, p)| (url.as_str(), p)))
             .entry(&"concurrent-resolve", &self.concurrent_resolve)
+            .entry(&"quorum", &self.quorum)
+            .entry(&"tiers", &self.tiers)
             .finish()
     }
 }
@@ -376,8 +901,8 @@ impl Serialize for Sources {
     {
         let mut map_serialize = serializer.serialize_map(Some(self.sources.len()))?;
 
-        for (url, proc) in self.sources.iter() {
-            map_serialize.serialize_entry(url.as_str(), proc)?
+        for (url, source) in self.sources.iter() {
+            map_serialize.serialize_entry(url.as_str(), &source.process)?
         }
 
         map_serialize.end()
@@ -386,16 +911,122 @@ impl Serialize for Sources {
 
 pub struct IpSource {
     url: Url,
+    kind: Arc<SourceKind>,
     process: Process,
 }
 
 impl IpSource {
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
     pub async fn resolve_ip(
         self,
         client: &RetryingClient,
         cfg: &Config,
     ) -> Result<Ipv4Addr, GetIpError> {
-        let bytes = client.get(self.url).send().await?.bytes().await?;
-        self.process.run(bytes, cfg).await
+        let fetched = self.kind.fetch(&self.url, client, cfg).await?;
+        self.process.run(fetched, cfg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quorum(query: u8, agree: u8) -> Quorum {
+        Quorum {
+            query: NonZeroU8::new(query).unwrap(),
+            agree: NonZeroU8::new(agree).unwrap(),
+        }
+    }
+
+    fn answers(ips: &[[u8; 4]]) -> Vec<(Url, Ipv4Addr)> {
+        ips.iter()
+            .enumerate()
+            .map(|(i, ip)| {
+                let url = Url::parse(&format!("https://ip{i}.example.com")).unwrap();
+                (url, Ipv4Addr::from(*ip))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quorum_picks_the_majority() {
+        let answers = answers(&[[192, 0, 2, 1], [203, 0, 113, 7], [192, 0, 2, 1]]);
+        let (ip, dissent) = quorum(3, 2).decide(answers.clone()).unwrap();
+        assert_eq!(ip, Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(dissent, [answers[1].clone()]);
+
+        let (ip, dissent) = quorum(1, 1).decide(answers[1..2].to_vec()).unwrap();
+        assert_eq!(ip, Ipv4Addr::new(203, 0, 113, 7));
+        assert!(dissent.is_empty());
+    }
+
+    #[test]
+    fn quorum_needs_enough_votes() {
+        let answers = answers(&[[192, 0, 2, 1], [192, 0, 2, 1], [203, 0, 113, 7]]);
+        let Err(GetIpError::NoQuorum(no_quorum)) = quorum(3, 3).decide(answers) else {
+            panic!("two out of three votes passed a quorum of three")
+        };
+        assert!(!no_quorum.tie);
+        assert_eq!(
+            no_quorum.to_string(),
+            "no quorum, needed 3 sources to agree but got 3 answers:\n\
+             https://ip0.example.com/ => 192.0.2.1\n\
+             https://ip1.example.com/ => 192.0.2.1\n\
+             https://ip2.example.com/ => 203.0.113.7"
+        );
+
+        assert!(quorum(2, 1).decide(vec![]).is_err());
+    }
+
+    #[test]
+    fn quorum_refuses_ties() {
+        // one vote each would satisfy `agree = 1`, but there is no way to pick between them
+        let answers = answers(&[[192, 0, 2, 1], [203, 0, 113, 7]]);
+        let Err(GetIpError::NoQuorum(no_quorum)) = quorum(2, 1).decide(answers) else {
+            panic!("a tie was broken")
+        };
+        assert!(no_quorum.tie);
+        assert!(
+            no_quorum
+                .to_string()
+                .starts_with("no quorum, several addresses got the same number of votes out of 2"),
+            "{no_quorum}"
+        );
+    }
+
+    #[test]
+    fn quorum_rejects_impossible_agreement() {
+        let q = toml::from_str::<Quorum>("query = 3\nagree = 2").unwrap();
+        assert_eq!(q, quorum(3, 2));
+
+        let err = toml::from_str::<Quorum>("query = 2\nagree = 3").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("can't require 3 sources to agree when only 2 are queried"),
+            "{err}"
+        );
+        assert!(toml::from_str::<Quorum>("query = 0\nagree = 0").is_err());
+    }
+
+    #[test]
+    fn splits_into_parts() {
+        let bytes = Bytes::from_static(b"a, b, , c");
+        let part = |delimiter: &str, index| nth_part(&bytes, delimiter.as_bytes(), index);
+
+        assert_eq!(part(", ", 0).unwrap(), "a");
+        assert_eq!(part(", ", 1).unwrap(), "b");
+        assert_eq!(part(", ", 2).unwrap(), "");
+        assert_eq!(part(", ", 3).unwrap(), "c");
+        assert_eq!(part(", ", 4), None);
+
+        // the whole input is the only part when the delimiter never shows up
+        assert_eq!(part(";", 0).unwrap(), "a, b, , c");
+        assert_eq!(part(";", 1), None);
+        // a delimiter at the very end leaves an empty last part
+        assert_eq!(nth_part(&Bytes::from_static(b"a\n"), b"\n", 1).unwrap(), "");
+        assert_eq!(nth_part(&Bytes::new(), b"\n", 0).unwrap(), "");
     }
 }
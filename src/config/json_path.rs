@@ -0,0 +1,368 @@
+use serde::de::{DeserializeSeed, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::de::SliceRead;
+use serde_json::Deserializer as JsonDeserializer;
+use std::fmt::{Debug, Display, Formatter};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum JsonPathError {
+    #[error("invalid escape in json pointer segment `{0}`")]
+    InvalidEscape(Box<str>),
+    #[error("unclosed `[` in `{0}`")]
+    UnclosedIndex(Box<str>),
+    #[error("`{0}` is not a valid array index")]
+    InvalidIndex(Box<str>),
+    #[error("empty segment in `{0}`")]
+    EmptySegment(Box<str>),
+}
+
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq)]
+enum Segment {
+    Key(Box<str>),
+    Index(usize),
+    /// json pointer tokens can address both object keys and array indices
+    Token(Box<str>),
+}
+
+impl Segment {
+    fn matches_key(&self, key: &str) -> bool {
+        match self {
+            Segment::Key(k) | Segment::Token(k) => **k == *key,
+            Segment::Index(_) => false,
+        }
+    }
+
+    fn matches_index(&self, index: usize) -> bool {
+        match self {
+            Segment::Index(i) => *i == index,
+            // leading zeros are not array indices in json pointers
+            Segment::Token(t) => t.parse() == Ok(index) && (index == 0 || !t.starts_with('0')),
+            Segment::Key(_) => false,
+        }
+    }
+}
+
+/// where to find the value in a json, either a json pointer like `/data/ips/0`,
+/// or a dotted path like `$.data.ips[0]`, anything else is a key of the top level object
+#[derive(Clone, PartialOrd, PartialEq, Ord, Eq)]
+pub struct JsonPath {
+    source: Box<str>,
+    segments: Box<[Segment]>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, JsonPathError> {
+        let segments = if let Some(pointer) = path.strip_prefix('/') {
+            Self::parse_pointer(pointer)?
+        } else if let Some(dotted) = path.strip_prefix("$.") {
+            Self::parse_dotted(dotted)?
+        } else if let Some(dotted) = path.strip_prefix('$').filter(|p| p.starts_with('[')) {
+            Self::parse_dotted(dotted)?
+        } else {
+            // keys with dots in them keep working like they did before paths
+            Box::new([Segment::Key(path.into())])
+        };
+
+        Ok(JsonPath {
+            source: path.into(),
+            segments,
+        })
+    }
+
+    fn parse_pointer(pointer: &str) -> Result<Box<[Segment]>, JsonPathError> {
+        pointer
+            .split('/')
+            .map(|token| {
+                let mut out = String::with_capacity(token.len());
+                let mut chars = token.chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '~' => match chars.next() {
+                            Some('0') => out.push('~'),
+                            Some('1') => out.push('/'),
+                            _ => return Err(JsonPathError::InvalidEscape(token.into())),
+                        },
+                        c => out.push(c),
+                    }
+                }
+                Ok(Segment::Token(out.into()))
+            })
+            .collect()
+    }
+
+    fn parse_dotted(path: &str) -> Result<Box<[Segment]>, JsonPathError> {
+        let mut segments = vec![];
+
+        for part in path.split('.') {
+            let (key, mut indices) = match part.find('[') {
+                Some(i) => part.split_at(i),
+                None => (part, ""),
+            };
+
+            match key.is_empty() {
+                // `[0].ip` on an array at the top level
+                true if segments.is_empty() && !indices.is_empty() => {}
+                true => return Err(JsonPathError::EmptySegment(path.into())),
+                false => segments.push(Segment::Key(key.into())),
+            }
+
+            while let Some(rest) = indices.strip_prefix('[') {
+                let (index, rest) = rest
+                    .split_once(']')
+                    .ok_or_else(|| JsonPathError::UnclosedIndex(path.into()))?;
+                let index = index
+                    .parse()
+                    .map_err(|_| JsonPathError::InvalidIndex(index.into()))?;
+                segments.push(Segment::Index(index));
+                indices = rest;
+            }
+
+            if !indices.is_empty() {
+                return Err(JsonPathError::InvalidIndex(indices.into()));
+            }
+        }
+
+        Ok(segments.into())
+    }
+
+    /// streams through the json, only the value at the end of the path is allocated
+    pub fn extract(&self, json: &[u8]) -> serde_json::Result<serde_json::Value> {
+        let mut deserializer = JsonDeserializer::new(SliceRead::new(json));
+        let value = PathSeed {
+            path: &self.segments,
+        }
+        .deserialize(&mut deserializer)?;
+        deserializer.end()?;
+
+        value.ok_or_else(|| serde_json::Error::custom(format_args!("missing field `{self}`")))
+    }
+}
+
+/// yields `None` if the path doesn't exist, so the caller can report the full path
+struct PathSeed<'a> {
+    path: &'a [Segment],
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for PathSeed<'a> {
+    type Value = Option<serde_json::Value>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        match self.path {
+            [] => serde_json::Value::deserialize(deserializer).map(Some),
+            _ => deserializer.deserialize_any(self),
+        }
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for PathSeed<'a> {
+    type Value = Option<serde_json::Value>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        match self.path {
+            [Segment::Index(_), ..] => formatter.write_str("an array"),
+            [Segment::Key(_), ..] => formatter.write_str("an object"),
+            _ => formatter.write_str("an object or an array"),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let [segment, rest @ ..] = self.path else {
+            return Ok(None);
+        };
+
+        let mut val = None;
+        while let Some(matches) = map.next_key_seed(KeyMatches(segment))? {
+            match val.is_none() && matches {
+                true => val = map.next_value_seed(PathSeed { path: rest })?,
+                false => _ = map.next_value::<IgnoredAny>()?,
+            }
+        }
+
+        Ok(val)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let [segment, rest @ ..] = self.path else {
+            return Ok(None);
+        };
+
+        let mut val = None;
+        let mut index = 0;
+        loop {
+            let found = match val.is_none() && segment.matches_index(index) {
+                true => seq
+                    .next_element_seed(PathSeed { path: rest })?
+                    .map(|x| val = x),
+                false => seq.next_element::<IgnoredAny>()?.map(|_| ()),
+            };
+
+            if found.is_none() {
+                return Ok(val);
+            }
+            index += 1;
+        }
+    }
+
+    // anything else can't be walked into
+    fn visit_bool<E: Error>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_i64<E: Error>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_u64<E: Error>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_f64<E: Error>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_str<E: Error>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+}
+
+/// compares an object key against a segment without allocating it
+struct KeyMatches<'a>(&'a Segment);
+
+impl<'de, 'a> DeserializeSeed<'de> for KeyMatches<'a> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for KeyMatches<'a> {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("an object key")
+    }
+
+    fn visit_str<E: Error>(self, key: &str) -> Result<Self::Value, E> {
+        Ok(self.0.matches_key(key))
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Debug for JsonPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        <str as Debug>::fmt(&self.source, f)
+    }
+}
+
+impl Serialize for JsonPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let path = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        JsonPath::parse(&path).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn extract(path: &str, json: &str) -> serde_json::Result<serde_json::Value> {
+        JsonPath::parse(path).unwrap().extract(json.as_bytes())
+    }
+
+    #[test]
+    fn extracts_dotted_paths() {
+        let json = r#"{"data": {"ips": ["192.0.2.1", "203.0.113.7"]}, "ip": "198.51.100.1"}"#;
+        assert_eq!(extract("$.data.ips[1]", json).unwrap(), "203.0.113.7");
+        assert_eq!(extract("$.ip", json).unwrap(), "198.51.100.1");
+        assert_eq!(
+            extract("$.data", json).unwrap(),
+            json!({"ips": ["192.0.2.1", "203.0.113.7"]})
+        );
+        assert_eq!(
+            extract("$[0].ip", r#"[{"ip": "192.0.2.1"}]"#).unwrap(),
+            "192.0.2.1"
+        );
+        assert_eq!(extract("$.a[0][1]", r#"{"a": [[1, 2]]}"#).unwrap(), 2);
+    }
+
+    #[test]
+    fn extracts_json_pointers() {
+        let json = r#"{"a/b": {"~c": ["192.0.2.1", "203.0.113.7"]}, "0": "key"}"#;
+        assert_eq!(extract("/a~1b/~0c/1", json).unwrap(), "203.0.113.7");
+        // a token is an object key as much as an array index
+        assert_eq!(extract("/0", json).unwrap(), "key");
+        assert_eq!(extract("/0", r#"["index"]"#).unwrap(), "index");
+        assert!(extract("/01", r#"["a", "b"]"#).is_err());
+    }
+
+    #[test]
+    fn falls_back_to_a_plain_key() {
+        let json = r#"{"ip.v4": "192.0.2.1", "ip": {"v4": "203.0.113.7"}}"#;
+        assert_eq!(extract("ip.v4", json).unwrap(), "192.0.2.1");
+        assert_eq!(extract("$.ip.v4", json).unwrap(), "203.0.113.7");
+    }
+
+    #[test]
+    fn takes_the_first_duplicate_key() {
+        let json = r#"{"ip": "192.0.2.1", "ip": "203.0.113.7"}"#;
+        assert_eq!(extract("$.ip", json).unwrap(), "192.0.2.1");
+    }
+
+    #[test]
+    fn reports_missing_values() {
+        for (path, json) in [
+            ("$.ip", r#"{"addr": "192.0.2.1"}"#),
+            ("$.data.ip", r#"{"data": "192.0.2.1"}"#),
+            ("$.ips[2]", r#"{"ips": ["192.0.2.1"]}"#),
+            ("$[0]", r#"{"0": "192.0.2.1"}"#),
+        ] {
+            let err = extract(path, json).unwrap_err().to_string();
+            assert!(err.contains(&format!("missing field `{path}`")), "{err}");
+        }
+
+        // the rest of the document still has to be valid json
+        assert!(extract("$.ip", r#"{"ip": "192.0.2.1", "#).is_err());
+        assert!(extract("$.ip", r#"{"ip": "192.0.2.1"} trailing"#).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        for (path, err) in [
+            ("/a~2", "invalid escape in json pointer segment `a~2`"),
+            ("/a~", "invalid escape in json pointer segment `a~`"),
+            ("$.ips[0", "unclosed `[` in `ips[0`"),
+            ("$.ips[a]", "`a` is not a valid array index"),
+            ("$.ips[0]x", "`x` is not a valid array index"),
+            ("$.a..b", "empty segment in `a..b`"),
+            ("$.", "empty segment in ``"),
+        ] {
+            assert_eq!(JsonPath::parse(path).unwrap_err().to_string(), err);
+        }
+    }
+
+    #[test]
+    fn displays_the_source() {
+        let path = JsonPath::parse("$.data.ips[0]").unwrap();
+        assert_eq!(path.to_string(), "$.data.ips[0]");
+        assert_eq!(format!("{path:?}"), r#""$.data.ips[0]""#);
+        assert_eq!(serde_json::to_value(&path).unwrap(), "$.data.ips[0]");
+    }
+}
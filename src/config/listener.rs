@@ -1,7 +1,7 @@
 use crate::config::ip_source::Sources;
 use crate::config::{deserialize_from_file, CfgInner, Config};
 use crate::updaters::{Updater, UpdatersManager};
-use crate::{non_zero, util, DdnsContext, UserMessages};
+use crate::{metrics, non_zero, util, DdnsContext, UserMessages};
 use anyhow::Result;
 use anyhow::{anyhow, Context};
 use arc_swap::ArcSwap;
@@ -133,10 +133,14 @@ async fn listen(
                                     let mut new_cfg = CfgInner::clone(&old_cfg);
                                     new_cfg.$part = Arc::new(part);
                                     cfg.store(Arc::new(new_cfg));
+                                    metrics::CONFIG_RELOADS.inc([$path, "ok"]);
                                     if $restart { return Ok(true); }
                                     if updater.update().is_err() { break }
                                 }
-                                Err(e) => msg_bx_handle.warning(format!("config listen error: {e}")).await
+                                Err(e) => {
+                                    metrics::CONFIG_RELOADS.inc([$path, "error"]);
+                                    msg_bx_handle.warning(format!("config listen error: {e}")).await
+                                }
                             }
                         }
                     };
@@ -155,7 +159,8 @@ async fn listen(
     anyhow::Ok(false)
 }
 
-pub async fn load() -> Result<(DdnsContext, UpdatersManager, ConfigStorage)> {
+/// creates `./config`, and fills in any missing config file with its default
+async fn ensure_config_files() -> Result<()> {
     if !util::try_exists("./config").await? {
         tokio::fs::create_dir_all("./config").await?;
     }
@@ -181,16 +186,10 @@ pub async fn load() -> Result<(DdnsContext, UpdatersManager, ConfigStorage)> {
         "./config/sources.toml", "../../includes/sources.toml";
     )?;
 
-    let ip_sources = match deserialize_from_file("./config/sources.toml").await {
-        Ok(x) => x,
-        Err(err) => {
-            UserMessages::new(non_zero!(1))
-                .warning(format!("{err}\n\n\n\n...Using default config..."))
-                .await;
-            Sources::default()
-        }
-    };
+    Ok(())
+}
 
+async fn read_config(ip_sources: Sources) -> Result<CfgInner> {
     macro_rules! load_config {
         ($($name:ident, $path:expr, $msg:expr $(;)+)*) => {
             $(let $name = deserialize_from_file($path)
@@ -205,17 +204,43 @@ pub async fn load() -> Result<(DdnsContext, UpdatersManager, ConfigStorage)> {
         api_fields, "./config/api.toml", "Invalid API Fields config";
     );
 
-    let cfg = Arc::new(CfgInner::new(
+    Ok(CfgInner::new(
         api_fields,
         http_config,
         services_config,
         ip_sources,
-    ));
+    ))
+}
+
+/// reads the config once without watching it, an invalid `sources.toml` is an error
+/// rather than falling back to the default sources
+pub async fn load_once() -> Result<Config> {
+    ensure_config_files().await?;
+    let ip_sources = deserialize_from_file("./config/sources.toml")
+        .await
+        .context("Invalid Sources config")?;
+    Ok(Config(Arc::new(read_config(ip_sources).await?)))
+}
+
+pub async fn load() -> Result<(DdnsContext, UpdatersManager, ConfigStorage)> {
+    ensure_config_files().await?;
+
+    let ip_sources = match deserialize_from_file("./config/sources.toml").await {
+        Ok(x) => x,
+        Err(err) => {
+            UserMessages::new(non_zero!(1))
+                .warning(format!("{err}\n\n\n\n...Using default config..."))
+                .await;
+            Sources::default()
+        }
+    };
+
+    let cfg = Arc::new(read_config(ip_sources).await?);
 
     let cfg_store = Arc::new(ArcSwap::new(Arc::clone(&cfg)));
     let cfg_weak = Arc::downgrade(&cfg_store);
 
-    let ctx = DdnsContext::new(Config(cfg));
+    let ctx = DdnsContext::new(Config(cfg))?;
     let user_messages = ctx.user_messages.clone();
     let mut updater_manager = UpdatersManager::new();
 
@@ -1,9 +1,14 @@
+use crate::config::cidr::{Cidr, BOGONS};
 use crate::config::time::Time;
 use crate::config::Deserializable;
 use anyhow::Result;
-use serde::Deserialize;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::num::NonZeroU8;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use thiserror::Error;
 
 #[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
 pub struct RefreshConfig {
@@ -12,6 +17,9 @@ pub struct RefreshConfig {
     #[serde(default = "RefreshConfig::default_network_detection")]
     #[serde(alias = "network-detection")]
     network_detection: bool,
+    #[serde(default = "RefreshConfig::default_verify_interval")]
+    #[serde(alias = "verify-interval")]
+    verify_interval: Time,
 }
 
 impl RefreshConfig {
@@ -25,18 +33,30 @@ impl RefreshConfig {
         true
     }
 
+    #[inline]
+    const fn default_verify_interval() -> Time {
+        Time(Duration::from_secs(6 * 60 * 60)) // 6 hours
+    }
+
     pub fn interval(&self) -> Duration {
         self.interval.0
     }
     pub fn network_detection(&self) -> bool {
         self.network_detection
     }
+    /// how often the cached record state is checked against cloudflare
+    pub fn verify_interval(&self) -> Duration {
+        self.verify_interval.0
+    }
 }
 
 #[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
 pub struct GeneralConfig {
     #[serde(default = "GeneralConfig::default_max_errors")]
     max_errors: NonZeroU8,
+    #[serde(default = "GeneralConfig::default_state_file")]
+    #[serde(alias = "state-file")]
+    state_file: PathBuf,
 }
 
 impl GeneralConfig {
@@ -45,15 +65,227 @@ impl GeneralConfig {
         unsafe { NonZeroU8::new_unchecked(5) }
     }
 
+    #[inline]
+    fn default_state_file() -> PathBuf {
+        PathBuf::from("./state.json")
+    }
+
     pub fn max_errors(&self) -> NonZeroU8 {
         self.max_errors
     }
+
+    pub fn state_file(&self) -> &Path {
+        &self.state_file
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("refusing to publish {ip}, it is a {reason} address ({range})")]
+pub struct RejectedAddress {
+    pub ip: Ipv4Addr,
+    pub range: Cidr,
+    pub reason: &'static str,
+}
+
+/// keeps addresses that can't be ours out of public dns
+#[derive(Debug, Default, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+pub struct ValidationConfig {
+    /// accepted even if they are in a private or reserved range
+    #[serde(default, deserialize_with = "ipv4_blocks")]
+    allow: Box<[Cidr]>,
+    /// rejected on top of the private and reserved ranges
+    #[serde(default, deserialize_with = "ipv4_blocks")]
+    deny: Box<[Cidr]>,
+}
+
+/// the detected address is always ipv4, so an ipv6 block would load fine and never match
+fn ipv4_blocks<'de, D>(deserializer: D) -> Result<Box<[Cidr]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let blocks = <Box<[Cidr]>>::deserialize(deserializer)?;
+    match blocks.iter().find(|block| !block.is_ipv4()) {
+        Some(block) => Err(D::Error::custom(format_args!(
+            "{block} isn't an ipv4 block, only ipv4 addresses are published"
+        ))),
+        None => Ok(blocks),
+    }
+}
+
+impl ValidationConfig {
+    pub fn check(&self, ip: Ipv4Addr) -> Result<(), RejectedAddress> {
+        let addr = IpAddr::V4(ip);
+        let reject = |range: Cidr, reason| Err(RejectedAddress { ip, range, reason });
+
+        if let Some(range) = self.deny.iter().find(|range| range.contains(addr)) {
+            return reject(*range, "denied");
+        }
+
+        if self.allow.iter().any(|range| range.contains(addr)) {
+            return Ok(());
+        }
+
+        match BOGONS.iter().find(|(range, _)| range.contains(addr)) {
+            Some((range, reason)) => reject(*range, reason),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+/// where log records end up, `journald` is only available on linux and `syslog` on unix
+#[derive(Debug, Default, Copy, Clone, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    #[default]
+    Stderr,
+    File,
+    Journald,
+    Syslog,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `<time> <LEVEL> <target>: <message> key=value...`
+    #[default]
+    Text,
+    /// one json object per line, for log shippers
+    Json,
+}
+
+#[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+pub struct LogConfig {
+    #[serde(default)]
+    level: LogLevel,
+    #[serde(default)]
+    sink: LogSink,
+    /// only used by the `file` sink
+    #[serde(default = "LogConfig::default_file")]
+    file: PathBuf,
+    /// journald keeps the fields on its own, so it ignores this
+    #[serde(default)]
+    format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: LogLevel::default(),
+            sink: LogSink::default(),
+            file: LogConfig::default_file(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl LogConfig {
+    #[inline]
+    fn default_file() -> PathBuf {
+        PathBuf::from("./cloudflare-ddns.log")
+    }
+
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+
+    pub fn sink(&self) -> LogSink {
+        self.sink
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+}
+
+/// the prometheus endpoint is only served if `listen` is set
+#[derive(Debug, Default, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    listen: Option<SocketAddr>,
+}
+
+impl MetricsConfig {
+    pub fn listen(&self) -> Option<SocketAddr> {
+        self.listen
+    }
+}
+
+/// `/healthz`, `/readyz` and `/status` are only served if `listen` is set
+#[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+pub struct StatusConfig {
+    #[serde(default)]
+    listen: Option<SocketAddr>,
+    /// `/readyz` fails once the last successful sync is this many refresh intervals old
+    #[serde(default = "StatusConfig::default_ready_within")]
+    #[serde(alias = "ready-within")]
+    ready_within: NonZeroU8,
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        StatusConfig {
+            listen: None,
+            ready_within: StatusConfig::default_ready_within(),
+        }
+    }
+}
+
+impl StatusConfig {
+    #[inline]
+    const fn default_ready_within() -> NonZeroU8 {
+        unsafe { NonZeroU8::new_unchecked(3) }
+    }
+
+    pub fn listen(&self) -> Option<SocketAddr> {
+        self.listen
+    }
+
+    pub fn ready_within(&self) -> NonZeroU8 {
+        self.ready_within
+    }
 }
 
 #[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
 pub struct MiscConfig {
     refresh: RefreshConfig,
     general: GeneralConfig,
+    #[serde(default)]
+    validation: ValidationConfig,
+    #[serde(default)]
+    log: LogConfig,
+    #[serde(default)]
+    metrics: MetricsConfig,
+    #[serde(default)]
+    status: StatusConfig,
 }
 
 impl MiscConfig {
@@ -64,6 +296,22 @@ impl MiscConfig {
     pub fn general(&self) -> &GeneralConfig {
         &self.general
     }
+
+    pub fn validation(&self) -> &ValidationConfig {
+        &self.validation
+    }
+
+    pub fn log(&self) -> &LogConfig {
+        &self.log
+    }
+
+    pub fn metrics(&self) -> &MetricsConfig {
+        &self.metrics
+    }
+
+    pub fn status(&self) -> &StatusConfig {
+        &self.status
+    }
 }
 
 impl Deserializable for MiscConfig {
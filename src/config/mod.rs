@@ -1,6 +1,6 @@
-use crate::config::api_fields::{Account, ApiFields, Auth, Zone};
+use crate::config::api_fields::{Account, ApiBaseUrl, ApiFields, Auth, Zone};
 use crate::config::http::HttpConfig;
-use crate::config::ip_source::{IpSource, Sources};
+use crate::config::ip_source::{IpSource, Quorum, Sources};
 use crate::config::misc::MiscConfig;
 use crate::retrying_client::{RequestBuilder, AUTHORIZATION_EMAIL, AUTHORIZATION_KEY};
 use reqwest::header::AUTHORIZATION;
@@ -9,11 +9,14 @@ use std::path::Path;
 use std::sync::Arc;
 
 pub mod api_fields;
-mod http;
+pub mod cidr;
+pub mod http;
 pub mod ip_source;
+mod json_path;
 pub mod listener;
-mod misc;
-mod time;
+pub mod misc;
+mod script;
+pub mod time;
 
 trait Deserializable: Sized {
     async fn deserialize(text: &str) -> anyhow::Result<Self>;
@@ -52,8 +55,19 @@ impl CfgInner {
 pub struct Config(Arc<CfgInner>);
 
 impl Config {
-    pub fn ip_sources(&self) -> impl Iterator<Item = IpSource> + '_ {
-        self.0.ip_sources.sources()
+    #[cfg(test)]
+    pub(crate) async fn from_toml(
+        api_fields: &str,
+        http: &str,
+        misc: &str,
+        ip_sources: &str,
+    ) -> anyhow::Result<Self> {
+        Ok(Config(Arc::new(CfgInner::new(
+            ApiFields::deserialize(api_fields).await?,
+            HttpConfig::deserialize(http).await?,
+            MiscConfig::deserialize(misc).await?,
+            Sources::deserialize(ip_sources).await?,
+        ))))
     }
 
     pub fn http(&self) -> &HttpConfig {
@@ -72,8 +86,16 @@ impl Config {
         &self.0.api_fields.account
     }
 
-    pub fn concurrent_resolve(&self) -> NonZeroU8 {
-        self.0.ip_sources.concurrent_resolve
+    pub fn api_base_url(&self) -> &ApiBaseUrl {
+        &self.0.api_fields.api_base_url
+    }
+
+    pub fn ip_source_tiers(&self) -> Vec<(NonZeroU8, Vec<IpSource>)> {
+        self.0.ip_sources.tiers()
+    }
+
+    pub fn quorum(&self) -> Option<Quorum> {
+        self.0.ip_sources.quorum
     }
 
     pub fn authorize_request(&self, request: RequestBuilder) -> RequestBuilder {
@@ -0,0 +1,187 @@
+use crate::config::ip_source::GetIpError;
+use crate::sources::Fetched;
+use bytes::Bytes;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+/// a cap on rhai operations backing up `MAX_RUNTIME`, whichever runs out first stops the script
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// the limit that actually applies, a script is stopped after this long
+const MAX_RUNTIME: Duration = Duration::from_millis(250);
+/// the deadline is checked every this many operations, reading the clock on every one is wasteful
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+thread_local! {
+    /// scripts run on the calling thread, so the deadline of the running script lives here
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut engine = Engine::new();
+    engine
+        // `import` would otherwise load and run `.rhai` files from disk
+        .set_module_resolver(DummyModuleResolver::new())
+        // scripts are compiled once when `sources.toml` is loaded, not at run time
+        .disable_symbol("eval")
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(64 * 1024)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000)
+        .on_progress(|operations| {
+            if operations % DEADLINE_CHECK_INTERVAL != 0 {
+                return None;
+            }
+            let expired = DEADLINE
+                .get()
+                .is_some_and(|deadline| Instant::now() > deadline);
+            expired.then(|| Dynamic::from("timed out"))
+        })
+        .on_print(|message| log::debug!("script: {message}"))
+        .on_debug(|message, _, _| log::debug!("script: {message}"));
+    engine
+});
+
+/// a rhai script that is compiled as soon as `sources.toml` is loaded, it sees the current data
+/// as `body`, the response headers as `headers` and the status as `status`, and returns the new data
+///
+/// ```toml
+/// steps = [{ Script = { code = '''
+///     let wan = parse_json(body).interfaces.filter(|x| x.name == "wan");
+///     wan[0].ipv4
+/// ''' } }]
+/// ```
+#[derive(Clone)]
+pub struct Script {
+    code: Box<str>,
+    ast: Arc<AST>,
+}
+
+impl Script {
+    pub fn new(code: &str) -> Result<Self, rhai::ParseError> {
+        ENGINE.compile(code).map(|ast| Script {
+            code: code.into(),
+            ast: Arc::new(ast),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.code
+    }
+
+    /// runs on a blocking thread, so a slow script can't hold up the runtime
+    pub async fn run(&self, body: &Bytes, fetched: &Fetched) -> Result<Bytes, GetIpError> {
+        let mut headers = Map::new();
+        for (name, value) in &fetched.headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            match headers.get_mut(name.as_str()) {
+                Some(joined) => *joined = format!("{joined}, {value}").into(),
+                None => _ = headers.insert(name.as_str().into(), value.into_owned().into()),
+            }
+        }
+
+        let mut scope = Scope::new();
+        scope
+            .push("body", String::from_utf8_lossy(body).into_owned())
+            .push("headers", headers)
+            .push(
+                "status",
+                fetched
+                    .status
+                    .map_or(Dynamic::UNIT, |status| (status.as_u16() as i64).into()),
+            );
+
+        let ast = Arc::clone(&self.ast);
+        tokio::task::spawn_blocking(move || {
+            DEADLINE.set(Some(Instant::now() + MAX_RUNTIME));
+            let result = ENGINE.eval_ast_with_scope::<Dynamic>(&mut scope, &ast);
+            DEADLINE.set(None);
+            Self::convert(result)
+        })
+        .await
+        .unwrap_or_else(|_| Err(GetIpError::Script("the script panicked".into())))
+    }
+
+    fn convert(result: Result<Dynamic, Box<EvalAltResult>>) -> Result<Bytes, GetIpError> {
+        let err = |msg: String| GetIpError::Script(msg.into());
+        let value = result.map_err(|e| match *e {
+            EvalAltResult::ErrorTerminated(..) => {
+                err(format!("the script ran for longer than {MAX_RUNTIME:?}"))
+            }
+            EvalAltResult::ErrorTooManyOperations(..) => err(format!(
+                "the script used up all of its {MAX_OPERATIONS} operations"
+            )),
+            e => err(format!("the script failed: {e}")),
+        })?;
+
+        let value = match value.try_cast_result::<String>() {
+            Ok(string) => return Ok(string.into()),
+            Err(value) => value,
+        };
+        let value = match value.try_cast_result::<rhai::Blob>() {
+            Ok(blob) => return Ok(blob.into()),
+            Err(value) => value,
+        };
+
+        match value.is_unit() {
+            true => Err(err("the script didn't return anything".into())),
+            false => Err(err(format!(
+                "the script returned a {} instead of a string",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Script {}
+
+impl PartialOrd for Script {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Script {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Debug for Script {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        <str as Debug>::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Script {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        Script::new(&code).map_err(|e| D::Error::custom(format_args!("invalid script: {e}")))
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Deserialize, Deserializer};
 use std::time::Duration;
 use toml::value::Datetime;
 
-#[derive(Debug, Eq, Ord, PartialOrd, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, Ord, PartialOrd, PartialEq)]
 pub struct Time(pub Duration);
 
 impl<'de> Deserialize<'de> for Time {
@@ -10,7 +10,18 @@ impl<'de> Deserialize<'de> for Time {
     where
         D: Deserializer<'de>,
     {
-        let val = Datetime::deserialize(deserializer)?;
+        // tables that went through a `toml::Value` hand us the datetime as a string
+        let val = match toml::Value::deserialize(deserializer)? {
+            toml::Value::Datetime(val) => val,
+            toml::Value::String(val) => {
+                val.parse::<Datetime>().map_err(serde::de::Error::custom)?
+            }
+            _ => {
+                return Err(serde::de::Error::custom(
+                    "expected a time value in the format of 'HH:MM:SS(.nnnnnnnnn optional)'",
+                ))
+            }
+        };
 
         match val {
             Datetime {
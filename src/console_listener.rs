@@ -1,8 +1,9 @@
 #[cfg(debug_assertions)]
 mod r#impl {
+    use crate::source_stats::SourceStats;
     use crate::updaters::{Updater, UpdatersManager};
     use std::convert::Infallible;
-    use std::sync::LazyLock;
+    use std::sync::{Arc, LazyLock};
     use std::{io, thread};
     use tokio::sync::mpsc::Receiver;
     use tokio::sync::Mutex;
@@ -13,7 +14,7 @@ mod r#impl {
         TriggerRestart,
     }
 
-    async fn listen(updater: &Updater) -> io::Result<Status> {
+    async fn listen(updater: &Updater, source_stats: &SourceStats) -> io::Result<Status> {
         // stdin is globally shared, so this also needs to be globally shared.
         // it won't end too well if we restart only to have to thread trying to read from stdin,
         // and we use a tokio mutex as we hold the receiver across a recv await point.
@@ -39,6 +40,7 @@ mod r#impl {
                         return Ok(Status::Success);
                     }
                 }
+                "sources" | "stats" => eprint!("{source_stats}"),
                 "exit" => return Ok(Status::TriggerExit),
                 "restart" => return Ok(Status::TriggerRestart),
                 _ => continue,
@@ -48,12 +50,15 @@ mod r#impl {
         Ok(Status::Success)
     }
 
-    pub fn subscribe(updaters_manager: &mut UpdatersManager) -> Result<(), Infallible> {
+    pub fn subscribe(
+        updaters_manager: &mut UpdatersManager,
+        source_stats: Arc<SourceStats>,
+    ) -> Result<(), Infallible> {
         let (updater, jh_entry) = updaters_manager.add_updater("console-listener");
         jh_entry.insert(tokio::spawn(async move {
             let res = tokio::select! {
                 _ = updater.wait_shutdown() => Ok(Status::Success),
-                res = listen(&updater) => res,
+                res = listen(&updater, &source_stats) => res,
             };
 
             match res {
@@ -70,11 +75,13 @@ mod r#impl {
 
 #[cfg(not(debug_assertions))]
 mod r#impl {
+    use crate::source_stats::SourceStats;
     use crate::updaters::UpdatersManager;
     use std::convert::Infallible;
+    use std::sync::Arc;
 
     #[inline]
-    pub fn subscribe(_: &mut UpdatersManager) -> Result<(), Infallible> {
+    pub fn subscribe(_: &mut UpdatersManager, _: Arc<SourceStats>) -> Result<(), Infallible> {
         Ok(())
     }
 }
@@ -1,5 +1,5 @@
 use std::borrow::Cow;
-use std::panic::PanicInfo;
+use std::panic::PanicHookInfo;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::thread;
@@ -8,13 +8,6 @@ use tokio::sync::Semaphore;
 
 pub mod exit;
 
-#[macro_export]
-macro_rules! dbg_println {
-    ($($arg:tt)*) => {
-        { #[cfg(debug_assertions)] { eprintln!($($arg)*) } }
-    };
-}
-
 fn spawn_thread(fun: impl FnOnce() + Send + 'static) {
     let handle = Handle::try_current();
     match handle {
@@ -143,177 +136,23 @@ mod sys {
 
 #[cfg(target_os = "linux")]
 mod sys {
-    // use gtk4::prelude::*;
-    // use gtk4::{Application, ApplicationWindow, ButtonsType, MessageDialog, MessageType};
-    use log::{Log, Metadata, Record};
-    use simple_logger::SimpleLogger;
-    use std::ops::Deref;
-    use std::sync::OnceLock;
-    use systemd_journal_logger::JournalLog;
-
-    enum OneOrTwo<T> {
-        One(T),
-        Two([T; 2]),
-    }
-
-    struct Loggers(OneOrTwo<Box<dyn Log>>);
-
-    impl Loggers {
-        fn iter(&self) -> impl Iterator<Item = &dyn Log> {
-            match &self.0 {
-                OneOrTwo::One(one) => std::slice::from_ref(one),
-                OneOrTwo::Two(two) => two,
-            }
-            .iter()
-            .map(Deref::deref)
-        }
-    }
-
-    impl Default for Loggers {
-        fn default() -> Self {
-            let simple = Box::new(SimpleLogger::new()) as Box<dyn Log>;
-            let system_d = JournalLog::new().map(Box::new);
-            let stuff = match system_d {
-                Ok(system_d) => OneOrTwo::Two([simple, system_d]),
-                Err(_) => OneOrTwo::One(simple),
-            };
-            Self(stuff)
-        }
-    }
-
-    impl Log for Loggers {
-        fn enabled(&self, metadata: &Metadata) -> bool {
-            self.iter().any(|logger| logger.enabled(metadata))
-        }
-
-        fn log(&self, record: &Record) {
-            self.iter().for_each(|logger| logger.log(record))
-        }
-
-        fn flush(&self) {
-            self.iter().for_each(Log::flush)
-        }
-    }
-
-    static LOGGERS: OnceLock<Loggers> = OnceLock::new();
-
-    static GTK_AVAILABLE: OnceLock<ErrorBackEnd> = OnceLock::new();
-
-    // type GtkMessage = (Box<dyn FnOnce() + Send>, OneShotSender<()>);
-    #[derive(Clone)]
-    enum ErrorBackEnd {
-        // Gtk(std::sync::mpsc::Sender<GtkMessage>),
-        Logger,
-    }
-
-    fn back_end() -> ErrorBackEnd {
-        GTK_AVAILABLE
-            .get_or_init(|| {
-                // let (send, rcv) = std::sync::mpsc::sync_channel(1);
-                // let _ = thread::Builder::new().spawn(move || {
-                //     if gtk4::init().is_err() {
-                //         let _ = send.send(Err(()));
-                //         return;
-                //     }
-                //     let (task_send, rcv) = std::sync::mpsc::channel::<GtkMessage>();
-                //     let _ = send.send(Ok(task_send));
-                //
-                //     for (msg, cb) in rcv {
-                //         msg();
-                //         let _ = cb.send(());
-                //     }
-                // });
-                //
-                // match rcv.recv() {
-                //     Ok(Ok(sender)) => ErrorBackEnd::Gtk(sender),
-                //     _ => {
-                //         log::set_logger(LOGGERS.get_or_init(Loggers::default))
-                //             .expect("unable to set any form of logging");
-                //         ErrorBackEnd::Logger
-                //     }
-                // }
-
-                log::set_logger(LOGGERS.get_or_init(Loggers::default))
-                    .expect("unable to set any form of logging");
-                ErrorBackEnd::Logger
-            })
-            .clone()
-    }
-
-    // fn gtk_present(title: String, msg: String, message_type: MessageType) {
-    //     let app = Application::builder()
-    //         .application_id("xyz.vrtgs.cloudflare-ddns.errors")
-    //         .build();
-    //
-    //     app.connect_activate(move |app| {
-    //         let window = ApplicationWindow::builder()
-    //             .application(app)
-    //             .title(&title)
-    //             .default_width(0)
-    //             .default_height(0)
-    //             .build();
-    //
-    //         let dialog = MessageDialog::builder()
-    //             .transient_for(&window)
-    //             .modal(true)
-    //             .buttons(ButtonsType::Close)
-    //             .message_type(message_type)
-    //             .text(&title)
-    //             .secondary_text(&msg)
-    //             .build();
-    //
-    //         let app = app.clone();
-    //         dialog.connect_response(move |dialog, _| {
-    //             dialog.close();
-    //             window.close();
-    //             app.quit();
-    //         });
-    //
-    //         dialog.show();
-    //     });
-    //
-    //     app.run();
-    // }
+    // there is no desktop to show an alert on, the log record is all there is
+    pub fn warn(_warning: &str) {}
 
-    fn present_alert(title: &str, msg: &str, message_type: log::Level) {
-        match back_end() {
-            // ErrorBackEnd::Gtk(chan) => {
-            //     let (title, msg) = (title.to_owned(), msg.to_owned());
-            //     let (send, wait) = std::sync::mpsc::sync_channel(1);
-            //     let _ = chan.send((
-            //         Box::new(move || gtk_present(title, msg, message_type)),
-            //         send,
-            //     ));
-            //     let _ = wait.recv();
-            // }
-            ErrorBackEnd::Logger => match message_type {
-                log::Level::Warn => log::warn!("[{title}]: {msg}"),
-                log::Level::Error => log::error!("[{title}]: {msg}"),
-                _ => unreachable!(),
-            },
-        }
-    }
-
-    pub fn warn(warning: &str) {
-        present_alert("CloudFlare DDNS Warning", warning, log::Level::Warn);
-    }
-
-    pub fn err(err: &str) {
-        present_alert("CloudFlare DDNS Error", err, log::Level::Error);
-    }
+    pub fn err(_err: &str) {}
 }
 
 #[cold]
 #[inline(never)]
 pub fn error(err: &str) {
-    dbg_println!("Error: {err}");
+    log::error!("{err}");
     sys::err(err)
 }
 
 #[cold]
 #[inline(never)]
 pub fn warn(warning: &str) {
-    dbg_println!("Warning: {warning}");
+    log::warn!("{warning}");
     sys::warn(warning)
 }
 
@@ -326,7 +165,7 @@ pub async fn spawn_message_box(semaphore: Arc<Semaphore>, err: impl FnOnce() + S
     }
 }
 
-fn hook(info: &PanicInfo) {
+fn hook(info: &PanicHookInfo) {
     macro_rules! try_cast {
         ([$payload:expr] $type: ty $(, $rest: ty)* |> $default: expr) => {
             match $payload.downcast_ref::<$type>() {
@@ -339,8 +178,6 @@ fn hook(info: &PanicInfo) {
 
     let msg = try_cast!([info.payload()] String,&str,Box<str>,Rc<str>,Arc<str>,Cow<str> |> "Box<dyn Any>");
 
-    dbg_println!("We panicked at: {msg}");
-
     match Handle::try_current().as_ref().map(Handle::runtime_flavor) {
         Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(|| error(msg)),
         _ => error(msg),
@@ -0,0 +1,176 @@
+use crate::updaters::UpdatersManager;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// a request line and its headers have to fit in this
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+/// slow clients are dropped rather than kept around
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: Box<str>,
+    /// without the query
+    pub path: Box<str>,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Response::new(404, "text/plain; charset=utf-8", "not found\n")
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Response::new(405, "text/plain; charset=utf-8", "method not allowed\n")
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+}
+
+/// binds `addr`, retrying for a moment in case the listener of the previous run
+/// is still shutting down after a restart
+pub async fn bind(addr: impl ToSocketAddrs + Copy) -> io::Result<TcpListener> {
+    let mut attempts = 0;
+    loop {
+        match TcpListener::bind(addr).await {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && attempts < 10 => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(200)).await
+            }
+            res => return res,
+        }
+    }
+}
+
+/// a tiny http/1.1 server, every connection serves exactly one `GET` or `HEAD` request
+pub async fn serve<F>(listener: TcpListener, handler: F) -> io::Result<Infallible>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            let _ = handle(stream, &*handler).await;
+        });
+    }
+}
+
+/// serves `handler` on `addr` as an updater, so it's shut down and restarted along with the rest
+pub fn subscribe<F>(
+    updaters_manager: &mut UpdatersManager,
+    name: &'static str,
+    addr: SocketAddr,
+    handler: F,
+) -> Result<(), Infallible>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let (updater, jh_entry) = updaters_manager.add_updater(name);
+    jh_entry.insert(tokio::spawn(async move {
+        let serve = async {
+            let listener = bind(addr).await?;
+            log::info!(updater = name, listen:% = addr; "listening");
+            serve(listener, handler).await
+        };
+
+        let res = tokio::select! {
+            res = serve => res.map(drop),
+            _ = updater.wait_shutdown() => Ok(()),
+        };
+        updater.exit(res)
+    }));
+
+    Ok(())
+}
+
+async fn read_head(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut head = Vec::with_capacity(512);
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_SIZE {
+            return Ok(None);
+        }
+        match stream.read(&mut buf).await? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    Ok(Some(head))
+}
+
+fn parse_request(head: &[u8]) -> Option<Request> {
+    let line = head.split(|&b| b == b'\r').next()?;
+    let mut parts = std::str::from_utf8(line).ok()?.split(' ');
+    let (method, target, _version) = (parts.next()?, parts.next()?, parts.next()?);
+    let path = target.split('?').next().unwrap_or(target);
+    Some(Request {
+        method: method.into(),
+        path: path.into(),
+    })
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    handler: &(dyn Fn(&Request) -> Response + Send + Sync),
+) -> io::Result<()> {
+    let head = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+    let request = head.as_deref().and_then(parse_request);
+    let (response, head_only) = match request {
+        None => (
+            Response::new(400, "text/plain; charset=utf-8", "bad request\n"),
+            false,
+        ),
+        Some(request) => match &*request.method {
+            "GET" => (handler(&request), false),
+            "HEAD" => (handler(&request), true),
+            _ => (Response::method_not_allowed(), false),
+        },
+    };
+
+    let mut out = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    if !head_only {
+        out.push_str(&response.body);
+    }
+
+    stream.write_all(out.as_bytes()).await?;
+    stream.shutdown().await
+}
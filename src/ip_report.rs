@@ -0,0 +1,66 @@
+use crate::config::ip_source::GetIpError;
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use url::Url;
+
+/// how a single source fared, a failing process step shows up as [`GetIpError::Step`]
+#[derive(Debug)]
+pub struct SourceReport {
+    pub url: Url,
+    pub latency: Duration,
+    pub result: Result<Ipv4Addr, GetIpError>,
+}
+
+/// every source that was asked while looking for our address, in the order they answered
+#[derive(Debug)]
+pub struct IpReport {
+    pub sources: Vec<SourceReport>,
+    pub outcome: Result<Ipv4Addr, GetIpError>,
+}
+
+impl IpReport {
+    /// the sources that answered with the address we settled on
+    pub fn winners(&self) -> impl Iterator<Item = &Url> + '_ {
+        let ip = self.outcome.as_ref().ok();
+        self.sources
+            .iter()
+            .filter(move |source| source.result.as_ref().ok() == ip)
+            .map(|source| &source.url)
+    }
+
+    pub fn into_result(self) -> anyhow::Result<Ipv4Addr> {
+        match self.outcome {
+            Ok(ip) => Ok(ip),
+            Err(_) => Err(anyhow::anyhow!("{self}")),
+        }
+    }
+}
+
+impl Display for IpReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            Ok(ip) => {
+                let winners = self.winners().map(Url::as_str).collect::<Vec<_>>();
+                write!(f, "{ip} from {}", winners.join(", "))?
+            }
+            Err(err) => write!(f, "{err}")?,
+        }
+
+        for source in &self.sources {
+            let status = match (&source.result, &self.outcome) {
+                (Ok(ip), Ok(chosen)) if ip == chosen => "ok",
+                (Ok(_), _) => "disagreed",
+                (Err(_), _) => "failed",
+            };
+
+            write!(f, "\n  {status:<9} {} ({:.0?})", source.url, source.latency)?;
+            match &source.result {
+                Ok(ip) => write!(f, " => {ip}")?,
+                Err(err) => write!(f, ": {err}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use crate::config::misc::{LogConfig, LogFormat, LogSink};
+use anyhow::{Context, Result};
+use arc_swap::ArcSwapOption;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// records from our dependencies are only let through from this level up, or `trace`
+/// would drown everything in tls and http internals
+const DEPENDENCY_LEVEL: Level = Level::Warn;
+
+enum Output {
+    Stderr,
+    File(Mutex<File>),
+    #[cfg(target_os = "linux")]
+    Journald(systemd_journal_logger::JournalLog),
+    #[cfg(unix)]
+    Syslog(std::os::unix::net::UnixDatagram),
+}
+
+struct Sink {
+    output: Output,
+    format: LogFormat,
+}
+
+/// until the config is loaded everything goes to stderr as text
+static SINK: ArcSwapOption<Sink> = ArcSwapOption::const_empty();
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let ours = metadata.target().starts_with(env!("CARGO_CRATE_NAME"));
+        metadata.level() <= log::max_level() && (ours || metadata.level() <= DEPENDENCY_LEVEL)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // there is nowhere left to report a failing sink to
+        let _ = match SINK.load().as_deref() {
+            Some(sink) => sink.write(record),
+            None => writeln!(io::stderr(), "{}", format_record(record, LogFormat::Text)),
+        };
+    }
+
+    fn flush(&self) {
+        if let Some(Sink {
+            output: Output::File(file),
+            ..
+        }) = SINK.load().as_deref()
+        {
+            let _ = file.lock().map(|mut file| file.flush());
+        }
+    }
+}
+
+impl Sink {
+    fn write(&self, record: &Record) -> io::Result<()> {
+        match &self.output {
+            Output::Stderr => writeln!(io::stderr(), "{}", format_record(record, self.format)),
+            Output::File(file) => {
+                let line = format_record(record, self.format) + "\n";
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                file.write_all(line.as_bytes())
+            }
+            #[cfg(target_os = "linux")]
+            Output::Journald(journal) => journal.journal_send(record),
+            #[cfg(unix)]
+            Output::Syslog(socket) => {
+                // daemon facility, and the severity the level maps to
+                let severity = match record.level() {
+                    Level::Error => 3,
+                    Level::Warn => 4,
+                    Level::Info => 6,
+                    Level::Debug | Level::Trace => 7,
+                };
+                let message = match self.format {
+                    LogFormat::Text => text(record, false),
+                    LogFormat::Json => json(record),
+                };
+                let packet = format!(
+                    "<{}>cloudflare-ddns[{}]: {message}",
+                    3 * 8 + severity,
+                    std::process::id()
+                );
+                socket.send(packet.as_bytes()).map(drop)
+            }
+        }
+    }
+}
+
+/// installs the logger, anything logged before the config is loaded goes to stderr
+pub fn init() {
+    if log::set_logger(&Logger).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+/// switches to the level, sink and format in `misc.toml`,
+/// the previous sink is kept if the new one can't be opened
+pub fn configure(cfg: &LogConfig) -> Result<()> {
+    let output = match cfg.sink() {
+        LogSink::Stderr => Output::Stderr,
+        LogSink::File => {
+            let file = File::options()
+                .create(true)
+                .append(true)
+                .open(cfg.file())
+                .with_context(|| format!("unable to open the log file {}", cfg.file().display()))?;
+            Output::File(Mutex::new(file))
+        }
+        LogSink::Journald => journald()?,
+        LogSink::Syslog => syslog()?,
+    };
+
+    SINK.store(Some(Arc::new(Sink {
+        output,
+        format: cfg.format(),
+    })));
+    log::set_max_level(cfg.level().into());
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn journald() -> Result<Output> {
+    let journal = systemd_journal_logger::JournalLog::new()
+        .context("unable to connect to journald")?
+        .with_syslog_identifier("cloudflare-ddns".to_owned());
+    Ok(Output::Journald(journal))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn journald() -> Result<Output> {
+    anyhow::bail!("journald is only available on linux")
+}
+
+#[cfg(unix)]
+fn syslog() -> Result<Output> {
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    let mut last_err = None;
+    for path in ["/dev/log", "/var/run/syslog", "/var/run/log"] {
+        match socket.connect(path) {
+            Ok(()) => return Ok(Output::Syslog(socket)),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap()).context("unable to connect to syslog")
+}
+
+#[cfg(not(unix))]
+fn syslog() -> Result<Output> {
+    anyhow::bail!("syslog is only available on unix")
+}
+
+/// renders a record as a single line, without the trailing newline
+pub fn format_record(record: &Record, format: LogFormat) -> String {
+    match format {
+        LogFormat::Text => text(record, true),
+        LogFormat::Json => json(record),
+    }
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+fn visit_fields(record: &Record, visit: impl FnMut(Key, Value)) {
+    struct Fields<F>(F);
+
+    impl<'kvs, F: FnMut(Key, Value)> VisitSource<'kvs> for Fields<F> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+            (self.0)(key, value);
+            Ok(())
+        }
+    }
+
+    let _ = record.key_values().visit(&mut Fields(visit));
+}
+
+/// `<time> <LEVEL> <target>: <message> key=value...`, values with spaces are quoted
+fn text(record: &Record, with_time: bool) -> String {
+    let mut line = String::new();
+    if with_time {
+        line.push_str(&now());
+        line.push(' ');
+    }
+
+    let _ = write!(
+        line,
+        "{:<5} {}: {}",
+        record.level(),
+        record.target(),
+        record.args()
+    );
+
+    visit_fields(record, |key, value| {
+        let value = value.to_string();
+        let needs_quotes =
+            value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=');
+        let _ = match needs_quotes {
+            true => write!(line, " {key}={value:?}"),
+            false => write!(line, " {key}={value}"),
+        };
+    });
+
+    line
+}
+
+/// one flat json object, the fields sit next to `time`, `level`, `target` and `message`
+fn json(record: &Record) -> String {
+    let mut object = serde_json::Map::new();
+    object.insert("time".into(), now().into());
+    object.insert("level".into(), record.level().as_str().into());
+    object.insert("target".into(), record.target().into());
+    object.insert("message".into(), record.args().to_string().into());
+
+    visit_fields(record, |key, value| {
+        object.insert(key.to_string(), json_value(&value));
+    });
+
+    serde_json::Value::Object(object).to_string()
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    if let Some(bool) = value.to_bool() {
+        return bool.into();
+    }
+    if let Some(int) = value.to_u64() {
+        return int.into();
+    }
+    if let Some(int) = value.to_i64() {
+        return int.into();
+    }
+    if let Some(number) = value.to_f64().and_then(serde_json::Number::from_f64) {
+        return number.into();
+    }
+    value.to_string().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[(&str, &dyn log::kv::ToValue)], format: LogFormat) -> String {
+        format_record(
+            &Record::builder()
+                .level(Level::Info)
+                .target("cloudflare_ddns")
+                .args(format_args!("updated the record"))
+                .key_values(&fields)
+                .build(),
+            format,
+        )
+    }
+
+    #[test]
+    fn formats_text() {
+        let fields: [(&str, &dyn log::kv::ToValue); 3] = [
+            ("record", &"home.example.com"),
+            ("source", &"https://ip.example.com/v1/me"),
+            ("latency_ms", &42u64),
+        ];
+
+        let text = record(&fields, LogFormat::Text);
+        assert!(
+            text.ends_with(
+                "INFO  cloudflare_ddns: updated the record record=home.example.com \
+                 source=https://ip.example.com/v1/me latency_ms=42"
+            ),
+            "{text}"
+        );
+        let (time, _) = text.split_once(' ').unwrap();
+        assert!(time.contains('T') && time.ends_with('Z'), "{text}");
+    }
+
+    #[test]
+    fn quotes_text_values() {
+        let fields: [(&str, &dyn log::kv::ToValue); 4] = [
+            ("error", &"timed out after 5s"),
+            ("empty", &""),
+            ("quote", &"say \"hi\""),
+            ("pair", &"a=b"),
+        ];
+
+        let text = record(&fields, LogFormat::Text);
+        assert!(
+            text.ends_with(r#"error="timed out after 5s" empty="" quote="say \"hi\"" pair="a=b""#),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn formats_json() {
+        let fields: [(&str, &dyn log::kv::ToValue); 4] = [
+            ("record", &"home.example.com"),
+            ("latency_ms", &42u64),
+            ("offset", &-3i64),
+            ("proxied", &true),
+        ];
+
+        let json = record(&fields, LogFormat::Json);
+        let json = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert!(json["time"].is_string(), "{json}");
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["target"], "cloudflare_ddns");
+        assert_eq!(json["message"], "updated the record");
+        assert_eq!(json["record"], "home.example.com");
+        assert_eq!(json["latency_ms"], 42);
+        assert_eq!(json["offset"], -3);
+        assert_eq!(json["proxied"], true);
+    }
+
+    #[test]
+    fn leaves_the_time_to_syslog() {
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("cloudflare_ddns")
+            .args(format_args!("no source answered"))
+            .build();
+        assert_eq!(
+            text(&record, false),
+            "WARN  cloudflare_ddns: no source answered"
+        );
+    }
+}
@@ -2,17 +2,22 @@
 
 extern crate core;
 
-use crate::config::ip_source::GetIpError;
+use crate::cloudflare::{
+    Cloudflare, CloudflareError, DnsRecord, PatchRecord, RecordQuery, RecordType,
+};
+use crate::config::ip_source::{GetIpError, IpSource, Quorum};
 use crate::config::Config;
+use crate::ip_report::{IpReport, SourceReport};
 use crate::network_listener::has_internet;
 use crate::retrying_client::RetryingClient;
+use crate::source_stats::SourceStats;
+use crate::state::{RecordState, StateGuard, StateStore};
+use crate::status::{Status, StatusApi};
 use crate::updaters::{UpdaterEvent, UpdaterExitStatus};
-use crate::util::{new_skip_interval, EscapeExt};
+use crate::util::new_skip_interval;
 use anyhow::{anyhow, Context, Result};
-use futures::StreamExt;
-use serde::Deserialize;
+use futures::{Stream, StreamExt};
 use std::borrow::Cow;
-use std::cell::Cell;
 use std::net::Ipv4Addr;
 use std::num::NonZeroU8;
 use std::panic::AssertUnwindSafe;
@@ -23,20 +28,36 @@ use std::thread;
 use std::thread::Builder;
 use std::time::Duration;
 use tokio::sync::Semaphore;
+use tokio::time::Instant;
 use tokio::try_join;
 
+mod cloudflare;
 mod config;
 mod console_listener;
 mod err;
+mod http_server;
+mod ip_report;
+mod logging;
+mod metrics;
 mod network_listener;
 mod pre;
 mod retrying_client;
+mod source_stats;
+mod sources;
+mod state;
+mod status;
+#[cfg(test)]
+mod tests;
+mod tls;
 mod updaters;
 mod util;
 
 struct DdnsContext {
     client: RetryingClient,
     user_messages: UserMessages,
+    state: StateStore,
+    source_stats: Arc<SourceStats>,
+    status: Arc<Status>,
 }
 
 #[derive(Debug)]
@@ -46,66 +67,186 @@ struct Record {
 }
 
 impl DdnsContext {
-    fn new(cfg: Config) -> Self {
-        DdnsContext {
-            client: RetryingClient::new(&cfg),
+    fn new(cfg: Config) -> Result<Self> {
+        Ok(DdnsContext {
+            client: RetryingClient::new(&cfg)?,
             user_messages: UserMessages::new(cfg.misc().general().max_errors()),
-        }
+            state: StateStore::new(cfg.misc().general().state_file()),
+            source_stats: Arc::default(),
+            status: Arc::default(),
+        })
     }
 
     async fn get_ip(&self, cfg: &Config) -> Result<Ipv4Addr> {
-        let last_err = Cell::new(None);
-
-        let iter = cfg.ip_sources().map(|x| x.resolve_ip(&self.client, cfg));
-        let stream = futures::stream::iter(iter)
-            .buffer_unordered(cfg.concurrent_resolve().get() as usize)
-            .filter_map(|x| {
-                std::future::ready({
-                    match x {
-                        Ok(x) => Some(x),
-                        Err(err) => {
-                            last_err.set(Some(err));
-                            None
-                        }
-                    }
-                })
+        let report = self.get_ip_report(cfg).await;
+        // a failure carries the whole report, and is reported by whoever handles it
+        if let Ok(ip) = report.outcome {
+            log::debug!("{report}");
+            self.status.detected(ip)
+        }
+        report.into_result()
+    }
+
+    /// looks for our address, and keeps track of what every source that was asked answered
+    async fn get_ip_report(&self, cfg: &Config) -> IpReport {
+        if let Some(quorum) = cfg.quorum() {
+            return self.get_ip_quorum(cfg, quorum).await;
+        }
+
+        let mut stream = pin!(self.resolve_tiers(cfg));
+        let mut sources = vec![];
+        while let Some(source) = stream.next().await {
+            let answer = source.result.as_ref().ok().copied();
+            sources.push(source);
+            if let Some(ip) = answer {
+                return IpReport {
+                    sources,
+                    outcome: Ok(ip),
+                };
+            }
+        }
+
+        IpReport {
+            outcome: Err(match sources.is_empty() {
+                true => GetIpError::NoIpSources,
+                false => GetIpError::NoAnswer,
+            }),
+            sources,
+        }
+    }
+
+    /// resolves the sources tier by tier, a tier is only started once the previous one ran out
+    fn resolve_tiers<'a>(&'a self, cfg: &'a Config) -> impl Stream<Item = SourceReport> + 'a {
+        let tiers = cfg
+            .ip_source_tiers()
+            .into_iter()
+            .map(move |(concurrent, sources)| {
+                let neutral = cfg.http().client().timeout();
+                let sources = self.source_stats.order(sources.into_iter(), neutral);
+                futures::stream::iter(sources.into_iter().map(move |x| self.resolve_ip(x, cfg)))
+                    .buffer_unordered(concurrent.get() as usize)
             });
 
-        pin!(stream)
-            .next()
-            .await
-            .ok_or_else(|| last_err.take().unwrap_or(GetIpError::NoIpSources).into())
+        futures::stream::iter(tiers).flatten()
     }
 
-    async fn get_record(&self, cfg: &Config) -> Result<Record> {
-        let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{zone_id}/dns_records?type=A&name={record}",
-            zone_id = cfg.zone().id(),
-            record = cfg.zone().record()
-        );
+    /// resolves a single source, keeping track of its health
+    async fn resolve_ip(&self, source: IpSource, cfg: &Config) -> SourceReport {
+        let url = source.url().clone();
+        let start = Instant::now();
+        let result = source.resolve_ip(&self.client, cfg).await.and_then(|ip| {
+            cfg.misc().validation().check(ip)?;
+            Ok(ip)
+        });
+        let latency = start.elapsed();
+        metrics::SOURCE_LATENCY.observe([url.as_str()], latency);
+        match &result {
+            Ok(ip) => {
+                log::debug!(
+                    source = url.as_str(),
+                    latency_ms = latency.as_millis() as u64,
+                    ip:% = ip;
+                    "source answered"
+                );
+                self.source_stats.record_success(&url, latency)
+            }
+            Err(err) => {
+                log::debug!(
+                    source = url.as_str(),
+                    latency_ms = latency.as_millis() as u64,
+                    error:% = err;
+                    "source failed"
+                );
+                metrics::SOURCE_ERRORS.inc([url.as_str()]);
+                self.source_stats.record_failure(&url)
+            }
+        }
+        SourceReport {
+            url,
+            latency,
+            result,
+        }
+    }
+
+    /// queries sources until `quorum.query` of them answered, and only accepts an address enough of them agree on
+    async fn get_ip_quorum(&self, cfg: &Config, quorum: Quorum) -> IpReport {
+        let mut stream = pin!(self.resolve_tiers(cfg));
+
+        let mut sources = vec![];
+        let mut answers = Vec::with_capacity(quorum.query.get() as usize);
+        while answers.len() < quorum.query.get() as usize {
+            let Some(source) = stream.next().await else {
+                break;
+            };
+            if let Ok(ip) = source.result {
+                answers.push((source.url.clone(), ip));
+            }
+            sources.push(source);
+        }
+
+        let failed = |sources, err| IpReport {
+            sources,
+            outcome: Err(err),
+        };
+
+        if answers.is_empty() {
+            return match sources.is_empty() {
+                true => failed(sources, GetIpError::NoIpSources),
+                false => failed(sources, GetIpError::NoAnswer),
+            };
+        }
+
+        let (ip, disagreeing) = match quorum.decide(answers.clone()) {
+            Ok(consensus) => consensus,
+            Err(err) => return failed(sources, err),
+        };
 
-        #[derive(Debug, Deserialize)]
-        struct FullATypeRecord {
-            id: Box<str>,
-            name: Box<str>,
-            #[serde(rename = "content")]
-            ip: Ipv4Addr,
+        for (url, answer) in &answers {
+            match *answer == ip {
+                true => self.source_stats.record_right_answer(url),
+                false => self.source_stats.record_wrong_answer(url),
+            }
+        }
+
+        if !disagreeing.is_empty() {
+            let disagreeing = disagreeing
+                .iter()
+                .map(|(url, ip)| format!("{url} => {ip}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            self.user_messages
+                .warning(format!(
+                    "some ip sources disagree with the agreed upon address {ip}:\n{disagreeing}"
+                ))
+                .await
         }
 
-        #[derive(Debug, Deserialize)]
-        pub struct GetResponse {
-            result: Vec<FullATypeRecord>,
+        IpReport {
+            sources,
+            outcome: Ok(ip),
         }
+    }
+
+    fn cloudflare<'a>(&'a self, cfg: &'a Config) -> Cloudflare<'a> {
+        Cloudflare::new(&self.client, cfg)
+    }
 
-        let records = cfg
-            .authorize_request(self.client.get(url))
-            .send()
-            .await?
-            .json::<GetResponse>()
-            .await?
-            .result;
+    async fn get_record(&self, cfg: &Config) -> Result<Record> {
+        let query = RecordQuery {
+            kind: Some(RecordType::A),
+            name: Some(cfg.zone().record()),
+            ..RecordQuery::default()
+        };
+
+        let records = self
+            .cloudflare(cfg)
+            .list_records(cfg.zone().id(), &query)
+            .await?;
 
-        let [FullATypeRecord { id, ip, name }] = <[FullATypeRecord; 1]>::try_from(records)
+        let [DnsRecord {
+            id, content, name, ..
+        }] = <[DnsRecord; 1]>::try_from(records)
             .map_err(|vec| anyhow!("expected 1 record got {} records: {vec:?}", vec.len()))?;
 
         anyhow::ensure!(
@@ -114,58 +255,142 @@ impl DdnsContext {
             cfg.zone().record()
         );
 
+        let ip = content
+            .parse::<Ipv4Addr>()
+            .with_context(|| format!("record {name} has a non ipv4 content {content}"))?;
+
         Ok(Record { id, ip })
     }
 
     async fn update_record(&self, id: &str, ip: Ipv4Addr, cfg: &Config) -> Result<()> {
-        let request_json = format! {
-            r###"{{"type":"A","name":"{record}","content":"{ip}","proxied":{proxied}}}"###,
-            record = cfg.zone().record().escape_json(),
-            proxied = cfg.zone().proxied()
-        };
-
-        let url = format! {
-            "https://api.cloudflare.com/client/v4/zones/{zone_id}/dns_records/{record_id}",
-            zone_id = cfg.zone().id(),
-            record_id = id
+        let content = ip.to_string();
+        let patch = PatchRecord {
+            kind: Some(RecordType::A),
+            name: Some(cfg.zone().record()),
+            content: Some(&content),
+            proxied: Some(cfg.zone().proxied()),
+            ..PatchRecord::default()
         };
 
-        let response = cfg
-            .authorize_request(self.client.patch(url))
-            .json(request_json)
-            .send()
+        self.cloudflare(cfg)
+            .patch_record(cfg.zone().id(), id, &patch)
             .await?;
 
-        let failure = !response.status().is_success();
-
-        let bytes = response
-            .bytes()
-            .await
-            .with_context(|| "unable to retrieve bytes")?;
+        Ok(())
+    }
 
-        #[derive(Debug, Deserialize)]
-        pub struct PatchResponse {
-            success: bool,
-        }
+    async fn save_state(
+        &self,
+        state: &mut StateGuard<'_>,
+        record: &Record,
+        cfg: &Config,
+        updated: bool,
+    ) {
+        let now = state::unix_now();
+        let updated_at = match state.get() {
+            _ if updated => now,
+            Some(old)
+                if old.matches(cfg) && old.record_id == record.id && old.content == record.ip =>
+            {
+                old.updated_at
+            }
+            _ => now,
+        };
 
-        let response = serde_json::from_slice::<PatchResponse>(&bytes)
-            .with_context(|| "unable to deserialize patch response json")?;
+        let new_state = RecordState {
+            zone_id: cfg.zone().id().into(),
+            record: cfg.zone().record().into(),
+            record_id: record.id.clone(),
+            content: record.ip,
+            updated_at,
+            verified_at: now,
+        };
 
-        if failure || !response.success {
-            anyhow::bail!("Bad response: {}", String::from_utf8_lossy(&bytes))
+        if let Err(err) = state.set(new_state).await {
+            self.user_messages
+                .warning(format!("unable to save the record state: {err}"))
+                .await
         }
+    }
 
-        Ok(())
+    /// [`DdnsContext::run_ddns`], keeping the metrics and the status api up to date
+    pub async fn sync(&self, cfg: Config) -> Result<bool> {
+        let record = cfg.zone().record().to_owned();
+        metrics::SYNC_ATTEMPTS.inc([&record]);
+
+        let res = self.run_ddns(cfg).await;
+        match &res {
+            Ok(changed) => {
+                metrics::SYNC_SUCCESSES.inc([&record]);
+                metrics::record_sync();
+                if *changed {
+                    metrics::IP_CHANGES.inc([&record]);
+                }
+                self.status.synced(self.state.lock().await.get().cloned());
+            }
+            Err(err) => {
+                metrics::SYNC_FAILURES.inc([&record]);
+                self.status.failed(err);
+            }
+        }
+        res
     }
 
     pub async fn run_ddns(&self, cfg: Config) -> Result<bool> {
-        let (record, current_ip) = try_join!(self.get_record(&cfg), self.get_ip(&cfg))?;
+        let mut state = self.state.lock().await;
+
+        let cached = state
+            .get()
+            .filter(|cached| cached.matches(&cfg) && !cached.needs_verification(&cfg))
+            .map(|cached| Record {
+                id: cached.record_id.clone(),
+                ip: cached.content,
+            });
+
+        let (record, current_ip) = match cached {
+            // trust our cache, and only talk to cloudflare if the ip changed
+            Some(record) => {
+                let current_ip = self.get_ip(&cfg).await?;
+                (record, current_ip)
+            }
+            None => {
+                let (record, current_ip) = try_join!(self.get_record(&cfg), self.get_ip(&cfg))?;
+                self.save_state(&mut state, &record, &cfg, false).await;
+                (record, current_ip)
+            }
+        };
 
         if record.ip == current_ip {
+            log::debug!(
+                record = cfg.zone().record(),
+                ip:% = current_ip;
+                "the record is up to date"
+            );
             return Ok(false);
         }
 
-        self.update_record(&record.id, current_ip, &cfg).await?;
+        metrics::UPDATE_ATTEMPTS.inc([cfg.zone().record()]);
+        if let Err(err) = self.update_record(&record.id, current_ip, &cfg).await {
+            metrics::UPDATE_FAILURES.inc([cfg.zone().record()]);
+            // the record might have been changed or removed behind our back
+            if let Err(state_err) = state.invalidate().await {
+                self.user_messages.warning(state_err.to_string()).await
+            }
+            return Err(err);
+        }
+        metrics::UPDATE_SUCCESSES.inc([cfg.zone().record()]);
+
+        log::info!(
+            record = cfg.zone().record(),
+            from:% = record.ip,
+            to:% = current_ip;
+            "updated the record"
+        );
+        let record = Record {
+            ip: current_ip,
+            ..record
+        };
+        self.save_state(&mut state, &record, &cfg, true).await;
         Ok(true)
     }
 }
@@ -211,34 +436,64 @@ enum Action {
 
 async fn real_main() -> Result<Action> {
     let (ctx, mut updaters_manager, cfg_store) = config::listener::load().await?;
+    if let Err(err) = logging::configure(cfg_store.load_config().misc().log()) {
+        ctx.user_messages
+            .warning(format!("{err:#}, keeping the previous log sink"))
+            .await
+    }
     let network_detection = cfg_store.load_config().misc().refresh().network_detection();
 
     if network_detection {
         network_listener::subscribe(&mut updaters_manager)?;
     }
+    let cfg = cfg_store.load_config();
+    if let Some(addr) = cfg.misc().metrics().listen() {
+        http_server::subscribe(
+            &mut updaters_manager,
+            "metrics-server",
+            addr,
+            metrics::handle,
+        )?;
+    }
+    if let Some(addr) = cfg.misc().status().listen() {
+        let api = StatusApi::new(Arc::clone(&ctx.status), Arc::clone(&ctx.source_stats), &cfg);
+        http_server::subscribe(&mut updaters_manager, "status-server", addr, move |req| {
+            api.handle(req)
+        })?;
+    }
     err::exit::subscribe(&mut updaters_manager)?;
-    console_listener::subscribe(&mut updaters_manager)?;
+    console_listener::subscribe(&mut updaters_manager, Arc::clone(&ctx.source_stats))?;
 
-    let mut interval = new_skip_interval(cfg_store.load_config().misc().refresh().interval());
+    let mut interval = new_skip_interval(cfg.misc().refresh().interval());
 
     loop {
         tokio::select! {
             _ = interval.tick() => {
+                ctx.status.ticked(interval.period());
                 if !has_internet().await {
-                    dbg_println!("no internet available skipping update");
+                    log::info!("no internet available, skipping the update");
                     continue;
                 }
 
-                dbg_println!("updating");
-                match ctx.run_ddns(cfg_store.load_config()).await {
-                    Err(err) => ctx.user_messages.error(err.to_string()).await,
-                    Ok(true) => dbg_println!("successfully updated"),
-                    Ok(false) => dbg_println!("IP didn't change skipping record update"),
+                log::debug!("updating");
+                match ctx.sync(cfg_store.load_config()).await {
+                    Err(err) => match err.downcast_ref::<CloudflareError>() {
+                        Some(CloudflareError::PinMismatch(pin)) => ctx.user_messages.error(format!(
+                            "refusing to talk to the Cloudflare API, the connection might be intercepted\n\n{pin}"
+                        )).await,
+                        _ => ctx.user_messages.error(err.to_string()).await,
+                    },
+                    Ok(true) => log::debug!("successfully updated"),
+                    Ok(false) => log::debug!("IP didn't change, skipping the record update"),
                 }
             },
             res = updaters_manager.watch() => match res {
-                UpdaterEvent::Update => interval.reset_immediately(),
+                UpdaterEvent::Update => {
+                    ctx.status.run_now();
+                    interval.reset_immediately()
+                }
                 UpdaterEvent::ServiceEvent(exit) => {
+                    metrics::record_updater_exit(exit.name(), exit.status());
                     match *exit.status() {
                         UpdaterExitStatus::Success => {},
                         UpdaterExitStatus::Panic | UpdaterExitStatus::Error(_) => {
@@ -270,11 +525,40 @@ fn make_runtime() -> tokio::runtime::Runtime {
         .expect("failed to build runtime")
 }
 
+/// `show-ip`, resolves our address once and prints what every source answered
+async fn show_ip() -> ExitCode {
+    let report = async {
+        let cfg = config::listener::load_once().await?;
+        logging::configure(cfg.misc().log())?;
+        let ctx = DdnsContext::new(cfg.clone())?;
+        anyhow::Ok(ctx.get_ip_report(&cfg).await)
+    }
+    .await;
+
+    match report {
+        Ok(report) => {
+            println!("{report}");
+            match report.outcome {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(_) => ExitCode::FAILURE,
+            }
+        }
+        Err(err) => {
+            eprintln!("{err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
 fn main() -> ExitCode {
     pre::pre_run();
     #[cfg(feature = "trace")]
     console_subscriber::init();
 
+    if std::env::args().nth(1).as_deref() == Some("show-ip") {
+        return make_runtime().block_on(show_ip());
+    }
+
     let mut runtime = make_runtime();
     loop {
         let exit = std::panic::catch_unwind(AssertUnwindSafe(|| runtime.block_on(real_main())));
@@ -282,31 +566,29 @@ fn main() -> ExitCode {
         match exit {
             // Non-Recoverable
             Ok(Ok(Action::Exit(exit))) => {
-                dbg_println!("Shutting down the runtime...");
+                log::debug!("shutting down the runtime...");
                 drop(runtime);
-                dbg_println!("Exiting...");
+                log::info!(code = exit; "exiting");
                 return ExitCode::from(exit);
             }
             Ok(Err(e)) => {
-                dbg_println!("Fatal init error");
-                dbg_println!("Aborting...");
+                log::error!("fatal init error, aborting...");
                 // best effort clean up
                 let _ = Builder::new().spawn(move || drop(runtime));
                 abort!("{e}")
             }
 
             // Recoverable
-            Ok(Ok(Action::Restart)) => dbg_println!("Restarting..."),
+            Ok(Ok(Action::Restart)) => log::info!("restarting..."),
             Err(_) => {
                 // old runtime might be in an invalid state
                 // replace it and drop it on a new thread to avoid hanging
                 let old_runtime = std::mem::replace(&mut runtime, make_runtime());
                 thread::spawn(move || drop(old_runtime));
 
-                dbg_println!("Panicked!!");
-                dbg_println!("Retrying in 15s...");
+                log::error!("panicked, retrying in 15s...");
                 thread::sleep(Duration::from_secs(15));
-                dbg_println!("Retrying")
+                log::info!("retrying")
             }
         }
     }
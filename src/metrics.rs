@@ -0,0 +1,245 @@
+use crate::http_server::{Request, Response};
+use crate::updaters::UpdaterExitStatus;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// upper bounds of the latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// a counter family, every distinct set of label values is its own series
+pub struct Counter<const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    labels: [&'static str; N],
+    series: Mutex<BTreeMap<[Box<str>; N], u64>>,
+}
+
+impl<const N: usize> Counter<N> {
+    const fn new(name: &'static str, help: &'static str, labels: [&'static str; N]) -> Self {
+        Counter {
+            name,
+            help,
+            labels,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, labels: [&str; N]) {
+        let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        *series.entry(labels.map(Box::from)).or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+        let series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        for (values, count) in series.iter() {
+            let labels = labels(&self.labels, values, None);
+            let _ = writeln!(out, "{}{labels} {count}", self.name);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    counts: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// a latency histogram family, in seconds
+pub struct Histogram<const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    labels: [&'static str; N],
+    series: Mutex<BTreeMap<[Box<str>; N], Buckets>>,
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new(name: &'static str, help: &'static str, labels: [&'static str; N]) -> Self {
+        Histogram {
+            name,
+            help,
+            labels,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, labels: [&str; N], value: Duration) {
+        let value = value.as_secs_f64();
+        let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        let buckets = series.entry(labels.map(Box::from)).or_default();
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&mut buckets.counts) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        buckets.sum += value;
+        buckets.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "histogram");
+        let series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        for (values, buckets) in series.iter() {
+            let name = self.name;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(buckets.counts) {
+                let labels = labels(&self.labels, values, Some(&bound.to_string()));
+                let _ = writeln!(out, "{name}_bucket{labels} {count}");
+            }
+            let inf = labels(&self.labels, values, Some("+Inf"));
+            let plain = labels(&self.labels, values, None);
+            let _ = writeln!(out, "{name}_bucket{inf} {}", buckets.count);
+            let _ = writeln!(out, "{name}_sum{plain} {}", buckets.sum);
+            let _ = writeln!(out, "{name}_count{plain} {}", buckets.count);
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+fn labels(names: &[&str], values: &[Box<str>], le: Option<&str>) -> String {
+    let pairs = names
+        .iter()
+        .zip(values.iter().map(|value| &**value))
+        .chain(le.map(|le| (&"le", le)))
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', r"\\")
+                .replace('"', r#"\""#)
+                .replace('\n', r"\n");
+            format!(r#"{name}="{value}""#)
+        })
+        .collect::<Vec<_>>();
+
+    match pairs.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", pairs.join(",")),
+    }
+}
+
+pub static SYNC_ATTEMPTS: Counter<1> = Counter::new(
+    "cloudflare_ddns_sync_attempts_total",
+    "times the record was checked against our address",
+    ["record"],
+);
+pub static SYNC_SUCCESSES: Counter<1> = Counter::new(
+    "cloudflare_ddns_sync_successes_total",
+    "checks that left the record up to date",
+    ["record"],
+);
+pub static SYNC_FAILURES: Counter<1> = Counter::new(
+    "cloudflare_ddns_sync_failures_total",
+    "checks that failed",
+    ["record"],
+);
+pub static UPDATE_ATTEMPTS: Counter<1> = Counter::new(
+    "cloudflare_ddns_update_attempts_total",
+    "record updates sent to cloudflare",
+    ["record"],
+);
+pub static UPDATE_SUCCESSES: Counter<1> = Counter::new(
+    "cloudflare_ddns_update_successes_total",
+    "record updates cloudflare accepted",
+    ["record"],
+);
+pub static UPDATE_FAILURES: Counter<1> = Counter::new(
+    "cloudflare_ddns_update_failures_total",
+    "record updates that failed",
+    ["record"],
+);
+pub static IP_CHANGES: Counter<1> = Counter::new(
+    "cloudflare_ddns_ip_changes_total",
+    "times the record was pointed at a new address",
+    ["record"],
+);
+pub static SOURCE_LATENCY: Histogram<1> = Histogram::new(
+    "cloudflare_ddns_source_latency_seconds",
+    "how long ip sources took to answer or fail",
+    ["source"],
+);
+pub static SOURCE_ERRORS: Counter<1> = Counter::new(
+    "cloudflare_ddns_source_errors_total",
+    "ip source lookups that failed, or gave a rejected address",
+    ["source"],
+);
+pub static HTTP_RETRIES: Counter<1> = Counter::new(
+    "cloudflare_ddns_http_retries_total",
+    "http requests that were retried",
+    ["host"],
+);
+pub static CONFIG_RELOADS: Counter<2> = Counter::new(
+    "cloudflare_ddns_config_reloads_total",
+    "changed config files that were read again",
+    ["file", "result"],
+);
+pub static UPDATER_EXITS: Counter<2> = Counter::new(
+    "cloudflare_ddns_updater_exits_total",
+    "background tasks that stopped",
+    ["updater", "status"],
+);
+
+static LAST_SYNC: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// the record is known to point at our address
+pub fn record_sync() {
+    *LAST_SYNC.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+}
+
+pub fn record_updater_exit(name: &str, status: &UpdaterExitStatus) {
+    let status = match status {
+        UpdaterExitStatus::Success => "success",
+        UpdaterExitStatus::Panic => "panic",
+        UpdaterExitStatus::TriggerRestart => "restart",
+        UpdaterExitStatus::TriggerExit(_) => "exit",
+        UpdaterExitStatus::Error(_) => "error",
+    };
+    UPDATER_EXITS.inc([name, status]);
+}
+
+/// everything in the prometheus text format
+pub fn render() -> String {
+    let mut out = String::new();
+    for counter in [
+        &SYNC_ATTEMPTS,
+        &SYNC_SUCCESSES,
+        &SYNC_FAILURES,
+        &UPDATE_ATTEMPTS,
+        &UPDATE_SUCCESSES,
+        &UPDATE_FAILURES,
+        &IP_CHANGES,
+        &SOURCE_ERRORS,
+        &HTTP_RETRIES,
+    ] {
+        counter.render(&mut out);
+    }
+    SOURCE_LATENCY.render(&mut out);
+    CONFIG_RELOADS.render(&mut out);
+    UPDATER_EXITS.render(&mut out);
+
+    // left out until the first sync, there is no sensible number before that
+    let name = "cloudflare_ddns_seconds_since_last_sync";
+    header(
+        &mut out,
+        name,
+        "seconds since the record was last known to be up to date",
+        "gauge",
+    );
+    if let Some(last) = *LAST_SYNC.lock().unwrap_or_else(|e| e.into_inner()) {
+        let _ = writeln!(out, "{name} {}", last.elapsed().as_secs_f64());
+    }
+
+    out
+}
+
+pub fn handle(request: &Request) -> Response {
+    match &*request.path {
+        "/metrics" => Response::new(200, "text/plain; version=0.0.4; charset=utf-8", render()),
+        _ => Response::not_found(),
+    }
+}
@@ -3,7 +3,6 @@
 #[cfg_attr(target_os = "macos", path = "macos.rs")]
 mod sys_common;
 
-use crate::dbg_println;
 use crate::updaters::{Updater, UpdatersManager};
 use crate::util::new_skip_interval_after;
 use ip_macro::ip;
@@ -66,7 +65,7 @@ async fn fallback_has_internet() -> bool {
 async fn fallback_listen(updater: &Updater) -> Result<(), Infallible> {
     let local_notify = Notify::new();
     let callback = || {
-        dbg_println!("Network Listener: got network update!");
+        log::debug!("network listener: got a network update");
         if updater.update().is_err() {
             local_notify.notify_waiters();
         }
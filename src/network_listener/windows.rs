@@ -3,8 +3,8 @@
 // huge thx to
 // https://github.com/suryatmodulus/firezone/blob/7c296494bd96c34ef1c0be75285ff92566f4c12c/rust/gui-client/src-tauri/src/client/network_changes.rs
 
+use crate::abort_unreachable;
 use crate::updaters::Updater;
-use crate::{abort_unreachable, dbg_println};
 use std::marker::{PhantomData, PhantomPinned};
 use std::pin::Pin;
 use tokio::runtime::Handle as TokioHandle;
@@ -153,7 +153,7 @@ pub fn subscribe(updater: Updater) -> JoinHandle<()> {
         let local_notify = Notify::new();
 
         let notify_callback = || {
-            dbg_println!("Network Listener: got network update!");
+            log::debug!("network listener: got a network update");
             if updater.update().is_err() {
                 local_notify.notify_waiters()
             }
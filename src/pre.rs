@@ -1,4 +1,4 @@
-use crate::err;
+use crate::{err, logging};
 
 #[cfg(target_os = "linux")]
 fn ensure_root() {
@@ -35,6 +35,7 @@ fn set_working_dir() {
 }
 
 pub fn pre_run() {
+    logging::init();
     err::set_hook();
     #[cfg(target_os = "linux")]
     ensure_root();
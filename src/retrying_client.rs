@@ -1,6 +1,9 @@
 use crate::abort_unreachable;
 use crate::config::Config;
-use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use crate::{metrics, tls};
+use anyhow::{Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use reqwest::{Body, Client, ClientBuilder, IntoUrl, Method, Request, Response};
 use std::time::Duration;
 
@@ -38,6 +41,26 @@ impl RequestBuilder {
         self
     }
 
+    pub fn basic_auth(self, username: &str, password: Option<&str>) -> RequestBuilder {
+        let credentials = format!("{username}:{}", password.unwrap_or_default());
+        let encoded = BASE64_STANDARD.encode(credentials);
+        match HeaderValue::from_str(&format!("Basic {encoded}")) {
+            Ok(mut value) => {
+                value.set_sensitive(true);
+                self.header(AUTHORIZATION, value)
+            }
+            Err(_) => self,
+        }
+    }
+
+    /// overrides the client timeout for this request
+    pub fn timeout(mut self, timeout: Duration) -> RequestBuilder {
+        if let Ok(ref mut req) = self.req {
+            *req.timeout_mut() = Some(timeout);
+        }
+        self
+    }
+
     pub fn json(self, body: impl Into<Body>) -> RequestBuilder {
         self.header(CONTENT_TYPE, JSON_MIME).body(body)
     }
@@ -58,7 +81,7 @@ pub struct RetryingClient {
 }
 
 impl RetryingClient {
-    pub fn new(cfg: &Config) -> Self {
+    pub fn new(cfg: &Config) -> Result<Self> {
         let _cfg = cfg;
         macro_rules! get {
             ($id: ident) => {
@@ -76,6 +99,15 @@ impl RetryingClient {
             .pool_max_idle_per_host(get!(max_idle_per_host))
             .use_rustls_tls();
 
+        let tls_config = cfg.http().tls();
+        let builder = match tls_config.is_custom() {
+            true => {
+                let api_host = cfg.api_base_url().host().unwrap_or_default();
+                builder.use_preconfigured_tls(tls::client_config(tls_config, api_host)?)
+            }
+            false => builder,
+        };
+
         #[cfg(feature = "trace")]
         let builder = builder
             .pool_idle_timeout(Duration::ZERO)
@@ -89,7 +121,7 @@ impl RetryingClient {
                 max_retries,
                 retry_interval,
             })
-            .unwrap_or_else(|e| abort_unreachable!("ClientBuilder failed {e}"))
+            .context("unable to build the http client")
     }
 
     /// See [`Client::get`]
@@ -97,11 +129,22 @@ impl RetryingClient {
         self.request(Method::GET, url)
     }
 
+    /// See [`Client::post`]
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
     /// See [`Client::patch`]
     pub fn patch<U: IntoUrl>(&self, url: U) -> RequestBuilder {
         self.request(Method::PATCH, url)
     }
 
+    /// See [`Client::delete`]
+    #[allow(dead_code)]
+    pub fn delete<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::DELETE, url)
+    }
+
     /// See [`Client::request`]
     pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
         RequestBuilder {
@@ -118,10 +161,13 @@ impl RetryingClient {
                 break;
             }
 
-            if let Some(req) = req.try_clone() {
-                match self.client.execute(req).await {
+            if let Some(attempt) = req.try_clone() {
+                match self.client.execute(attempt).await {
                     Ok(resp) => return Ok(resp),
+                    // a pin mismatch won't fix itself by retrying
+                    Err(e) if tls::PinMismatch::find(&e).is_some() => return Err(e),
                     Err(_) => {
+                        metrics::HTTP_RETRIES.inc([req.url().host_str().unwrap_or_default()]);
                         let sleep_for = self
                             .retry_interval
                             .checked_mul((i / 2).max(1) as u32)
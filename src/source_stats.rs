@@ -0,0 +1,188 @@
+use crate::config::ip_source::IpSource;
+use ahash::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+/// weight of the newest sample in the moving averages
+const SMOOTHING: f64 = 0.25;
+/// consecutive failures after which a source is only tried after the healthy ones
+const DEMOTE_AFTER: u32 = 2;
+/// consecutive failures after which a source is temporarily disabled
+const DISABLE_AFTER: u32 = 5;
+const MIN_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+const MAX_COOLDOWN: Duration = Duration::from_secs(6 * 60 * 60);
+/// what a source that always fails or is always outvoted costs, in multiples of the neutral latency
+const FAILURE_PENALTY: f64 = 4.0;
+const WRONG_PENALTY: f64 = 8.0;
+
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub successes: u64,
+    pub failures: u64,
+    pub wrong_answers: u64,
+    pub consecutive_failures: u32,
+    /// moving average of successful requests
+    pub latency: Option<Duration>,
+    /// moving average where 1.0 means every request failed
+    pub failure_rate: f64,
+    /// moving average where 1.0 means every answer was outvoted by the quorum
+    pub wrong_rate: f64,
+    pub disabled_until: Option<Instant>,
+}
+
+impl Stats {
+    fn smooth(avg: f64, sample: f64) -> f64 {
+        avg * (1.0 - SMOOTHING) + sample * SMOOTHING
+    }
+
+    fn is_disabled(&self, now: Instant) -> bool {
+        self.disabled_until.is_some_and(|until| until > now)
+    }
+
+    pub fn is_demoted(&self) -> bool {
+        self.consecutive_failures >= DEMOTE_AFTER
+    }
+
+    /// lower is better, in seconds, sources that never answered are assumed to take `neutral`
+    fn score(&self, neutral: Duration) -> f64 {
+        let neutral = neutral.as_secs_f64();
+        let latency = self
+            .latency
+            .map_or(neutral, |latency| latency.as_secs_f64());
+        latency
+            + neutral * FAILURE_PENALTY * self.failure_rate
+            + neutral * WRONG_PENALTY * self.wrong_rate
+    }
+}
+
+/// health of every ip source, used to prefer fast and reliable sources
+#[derive(Default)]
+pub struct SourceStats {
+    stats: Mutex<HashMap<Url, Stats>>,
+}
+
+impl SourceStats {
+    fn with<R>(&self, url: &Url, fun: impl FnOnce(&mut Stats) -> R) -> R {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = match stats.get_mut(url) {
+            Some(stats) => stats,
+            None => stats.entry(url.clone()).or_default(),
+        };
+        fun(stats)
+    }
+
+    pub fn record_success(&self, url: &Url, latency: Duration) {
+        self.with(url, |stats| {
+            stats.successes += 1;
+            stats.consecutive_failures = 0;
+            stats.disabled_until = None;
+            stats.failure_rate = Stats::smooth(stats.failure_rate, 0.0);
+            stats.latency = Some(match stats.latency {
+                Some(avg) => {
+                    Duration::from_secs_f64(Stats::smooth(avg.as_secs_f64(), latency.as_secs_f64()))
+                }
+                None => latency,
+            });
+        })
+    }
+
+    pub fn record_failure(&self, url: &Url) {
+        self.with(url, |stats| {
+            stats.failures += 1;
+            stats.consecutive_failures += 1;
+            stats.failure_rate = Stats::smooth(stats.failure_rate, 1.0);
+
+            if let Some(over) = stats.consecutive_failures.checked_sub(DISABLE_AFTER) {
+                let cooldown = MIN_COOLDOWN
+                    .checked_mul(1 << over.min(16))
+                    .map_or(MAX_COOLDOWN, |cooldown| cooldown.min(MAX_COOLDOWN));
+                stats.disabled_until = Some(Instant::now() + cooldown);
+            }
+        })
+    }
+
+    /// the source answered, but the quorum outvoted it
+    pub fn record_wrong_answer(&self, url: &Url) {
+        self.with(url, |stats| {
+            stats.wrong_answers += 1;
+            stats.wrong_rate = Stats::smooth(stats.wrong_rate, 1.0);
+        })
+    }
+
+    /// the source answered, and the quorum agreed with it
+    pub fn record_right_answer(&self, url: &Url) {
+        self.with(url, |stats| {
+            stats.wrong_rate = Stats::smooth(stats.wrong_rate, 0.0);
+        })
+    }
+
+    /// orders sources healthiest first, and leaves out disabled ones,
+    /// if every source is disabled they are all returned as a last resort,
+    /// `neutral` stands in for the latency of sources that never answered
+    pub fn order(
+        &self,
+        sources: impl Iterator<Item = IpSource>,
+        neutral: Duration,
+    ) -> Vec<IpSource> {
+        let now = Instant::now();
+        let stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut sources = sources
+            .map(|source| {
+                let stats = stats.get(source.url()).cloned().unwrap_or_default();
+                (stats, source)
+            })
+            .collect::<Vec<_>>();
+
+        if sources.iter().any(|(stats, _)| !stats.is_disabled(now)) {
+            sources.retain(|(stats, _)| !stats.is_disabled(now));
+        }
+
+        // stable sort, so equally healthy sources keep the config order
+        sources.sort_by(|(a, _), (b, _)| {
+            a.is_demoted()
+                .cmp(&b.is_demoted())
+                .then_with(|| a.score(neutral).total_cmp(&b.score(neutral)))
+        });
+
+        sources.into_iter().map(|(_, source)| source).collect()
+    }
+
+    pub fn snapshot(&self) -> Vec<(Url, Stats)> {
+        let stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        let mut snapshot = stats
+            .iter()
+            .map(|(url, stats)| (url.clone(), stats.clone()))
+            .collect::<Vec<_>>();
+        snapshot.sort_by(|(a, _), (b, _)| a.cmp(b));
+        snapshot
+    }
+}
+
+impl Display for SourceStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let now = Instant::now();
+        for (url, stats) in self.snapshot() {
+            write!(
+                f,
+                "{url}: {} ok, {} failed, {} wrong",
+                stats.successes, stats.failures, stats.wrong_answers
+            )?;
+            if let Some(latency) = stats.latency {
+                write!(f, ", ~{}ms", latency.as_millis())?;
+            }
+            match stats.disabled_until {
+                Some(until) if until > now => {
+                    write!(f, ", disabled for {}s", (until - now).as_secs())?
+                }
+                _ if stats.is_demoted() => write!(f, ", demoted")?,
+                _ => {}
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::ip_source::GetIpError;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, RecordType};
+use std::str::FromStr;
+use url::Url;
+
+const DNS_PORT: u16 = 53;
+
+/// `dns://resolver[:port]/name[?type=A|TXT&class=IN|CH]`,
+/// asks `resolver` about `name`, resolvers like opendns answer with the address they saw us from
+pub struct DnsQuery {
+    resolver: Box<str>,
+    port: u16,
+    name: Name,
+    kind: RecordType,
+    class: DNSClass,
+}
+
+impl DnsQuery {
+    pub fn parse(url: &Url) -> Result<Self> {
+        let resolver = url
+            .host_str()
+            .with_context(|| format!("{url} is missing the resolver to ask"))?;
+
+        let name = url.path().trim_start_matches('/');
+        anyhow::ensure!(!name.is_empty(), "{url} is missing the name to query");
+        let name = Name::from_ascii(name).with_context(|| format!("invalid name in {url}"))?;
+
+        let mut kind = RecordType::A;
+        let mut class = DNSClass::IN;
+        for (key, value) in url.query_pairs() {
+            let value = value.to_ascii_uppercase();
+            match &*key {
+                "type" => kind = RecordType::from_str(&value)?,
+                "class" => class = DNSClass::from_str(&value)?,
+                key => anyhow::bail!("unknown parameter `{key}` in {url}"),
+            }
+        }
+
+        anyhow::ensure!(
+            matches!(kind, RecordType::A | RecordType::TXT),
+            "only A and TXT records can be queried, {url} asks for {kind}"
+        );
+
+        Ok(DnsQuery {
+            resolver: resolver.trim_matches(['[', ']']).into(),
+            port: url.port().unwrap_or(DNS_PORT),
+            name,
+            kind,
+            class,
+        })
+    }
+
+    fn message(&self, id: u16) -> Result<Vec<u8>, GetIpError> {
+        let mut query = Query::query(self.name.clone(), self.kind);
+        query.set_query_class(self.class);
+
+        let mut message = Message::new();
+        message
+            .set_id(id)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(query);
+
+        Ok(message.to_vec()?)
+    }
+
+    /// every answer on its own line, addresses as text and txt records as their raw bytes
+    pub async fn resolve(&self, cfg: &Config) -> Result<Bytes, GetIpError> {
+        let addr = super::resolve_v4(&self.resolver, self.port).await?;
+
+        let id = u16::from_ne_bytes(super::random());
+        let request = self.message(id)?;
+        let socket = super::connect_udp(addr).await?;
+        let response = super::udp_exchange(&socket, &request, cfg.http().client().timeout(), |x| {
+            x.get(..2) == Some(&id.to_be_bytes())
+        })
+        .await?;
+
+        let response = Message::from_vec(&response)?;
+        if response.response_code() != ResponseCode::NoError {
+            return Err(GetIpError::Dns(
+                format!(
+                    "{} answered with {}",
+                    self.resolver,
+                    response.response_code()
+                )
+                .into(),
+            ));
+        }
+
+        let mut out = Vec::new();
+        for answer in response.answers() {
+            match answer.data() {
+                Some(RData::A(a)) => out.extend_from_slice(a.0.to_string().as_bytes()),
+                Some(RData::TXT(txt)) => {
+                    txt.txt_data().iter().for_each(|x| out.extend_from_slice(x))
+                }
+                _ => continue,
+            }
+            out.push(b'\n');
+        }
+
+        match out.pop() {
+            Some(_) => Ok(out.into()),
+            None => Err(GetIpError::Dns(
+                format!(
+                    "{} has no {} record for {}",
+                    self.resolver, self.kind, self.name
+                )
+                .into(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use super::http::HttpQuery;
+use super::read_capped;
+use crate::abort_unreachable;
+use crate::config::ip_source::GetIpError;
+use crate::config::time::Time;
+use crate::config::Config;
+use bytes::Bytes;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::io;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// runs a command and takes its stdout, for setups that already have a script printing the address
+///
+/// ```toml
+/// [modem]
+/// exec = ["snmpget", "-v2c", "-c", "public", "-Oqv", "192.168.100.1", "IP-MIB::ipAdEntAddr.1"]
+/// timeout = 00:00:05
+/// ```
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq, Deserialize)]
+pub struct ExecQuery {
+    #[serde(deserialize_with = "command")]
+    exec: Box<[Box<str>]>,
+    /// defaults to the http timeout
+    #[serde(default)]
+    timeout: Option<Time>,
+}
+
+fn command<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Box<[Box<str>]>, D::Error> {
+    let command = <Box<[Box<str>]>>::deserialize(deserializer)?;
+    match command.is_empty() {
+        true => Err(D::Error::custom("exec needs at least the program to run")),
+        false => Ok(command),
+    }
+}
+
+impl ExecQuery {
+    fn err(&self, msg: impl std::fmt::Display) -> GetIpError {
+        GetIpError::Exec(format!("`{}` {msg}", self.exec.join(" ")).into())
+    }
+
+    pub async fn resolve(&self, cfg: &Config) -> Result<Bytes, GetIpError> {
+        let timeout = self
+            .timeout
+            .map_or_else(|| cfg.http().client().timeout(), |time| time.0);
+
+        let mut child = Command::new(&*self.exec[0])
+            .args(self.exec[1..].iter().map(|arg| &**arg))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| self.err(format_args!("couldn't be started: {e}")))?;
+
+        let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+            abort_unreachable!("the output of a child is always piped")
+        };
+
+        // a runaway command is killed on drop instead of being buffered in full every tick
+        let limit = HttpQuery::DEFAULT_MAX_BODY_SIZE;
+        let stdout = async {
+            read_capped(stdout, limit).await?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("printed more than the {limit} byte limit"),
+                )
+            })
+        };
+        let output = async { tokio::try_join!(stdout, read_capped(stderr, limit), child.wait()) };
+
+        let (stdout, stderr, status) = tokio::time::timeout(timeout, output)
+            .await
+            .map_err(|_| self.err(format_args!("timed out after {timeout:?}")))?
+            .map_err(|e| self.err(e))?;
+
+        if !status.success() {
+            let stderr = stderr.as_deref().map(String::from_utf8_lossy);
+            return Err(self.err(format_args!(
+                "failed with {status}: {}",
+                stderr.as_deref().unwrap_or_default().trim()
+            )));
+        }
+
+        Ok(stdout)
+    }
+}
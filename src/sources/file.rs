@@ -0,0 +1,35 @@
+use super::http::HttpQuery;
+use super::read_capped;
+use crate::config::ip_source::GetIpError;
+use bytes::Bytes;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// reads a file, for routers or other daemons that write the address somewhere
+///
+/// ```toml
+/// [router]
+/// file = "/var/run/wan-ip"
+/// ```
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq, Deserialize)]
+pub struct FileQuery {
+    file: PathBuf,
+}
+
+impl FileQuery {
+    pub async fn resolve(&self) -> Result<Bytes, GetIpError> {
+        let err = |msg: std::fmt::Arguments| {
+            GetIpError::File(format!("unable to read {}: {msg}", self.file.display()).into())
+        };
+        let limit = HttpQuery::DEFAULT_MAX_BODY_SIZE;
+
+        let file = tokio::fs::File::open(&self.file)
+            .await
+            .map_err(|e| err(format_args!("{e}")))?;
+
+        read_capped(file, limit)
+            .await
+            .map_err(|e| err(format_args!("{e}")))?
+            .ok_or_else(|| err(format_args!("it is larger than the {limit} byte limit")))
+    }
+}
@@ -0,0 +1,170 @@
+use super::Fetched;
+use crate::config::ip_source::GetIpError;
+use crate::config::time::Time;
+use crate::retrying_client::RetryingClient;
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Method, Response};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::pin::pin;
+use url::Url;
+
+/// how `http://` and `https://` sources are requested, everything is optional
+///
+/// ```toml
+/// ["https://ip.example.com/v1/me"]
+/// method = "POST"
+/// headers = { x-api-key = "..." }
+/// basic-auth = { username = "me", password = "..." }
+/// timeout = 00:00:02
+/// max-body-size = 1024
+/// ```
+#[derive(Debug, Default, PartialOrd, PartialEq, Ord, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpQuery {
+    #[serde(default)]
+    method: HttpMethod,
+    #[serde(default)]
+    headers: Headers,
+    /// defaults to the http timeout
+    #[serde(default)]
+    timeout: Option<Time>,
+    #[serde(default, alias = "basic-auth")]
+    basic_auth: Option<BasicAuth>,
+    /// in bytes, defaults to [`HttpQuery::DEFAULT_MAX_BODY_SIZE`]
+    #[serde(default, alias = "max-body-size")]
+    max_body_size: Option<u64>,
+}
+
+#[derive(Debug, PartialOrd, PartialEq, Ord, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BasicAuth {
+    username: Box<str>,
+    #[serde(default)]
+    password: Option<Box<str>>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct HttpMethod(Method);
+
+impl Ord for HttpMethod {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.as_str().cmp(other.0.as_str())
+    }
+}
+
+impl PartialOrd for HttpMethod {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'de> Deserialize<'de> for HttpMethod {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let method = <Box<str>>::deserialize(deserializer)?;
+        Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+            .map(HttpMethod)
+            .map_err(|_| D::Error::custom(format_args!("invalid http method `{method}`")))
+    }
+}
+
+/// validated when `sources.toml` is loaded, so a typo can't fail every request
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Headers(Box<[(HeaderName, HeaderValue)]>);
+
+impl Ord for Headers {
+    fn cmp(&self, other: &Self) -> Ordering {
+        fn key((name, value): &(HeaderName, HeaderValue)) -> (&str, &[u8]) {
+            (name.as_str(), value.as_bytes())
+        }
+        self.0.iter().map(key).cmp(other.0.iter().map(key))
+    }
+}
+
+impl PartialOrd for Headers {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'de> Deserialize<'de> for Headers {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        BTreeMap::<Box<str>, Box<str>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| D::Error::custom(format_args!("invalid header name `{name}`")))?;
+                let mut value = HeaderValue::from_str(&value)
+                    .map_err(|_| D::Error::custom(format_args!("invalid value for `{name}`")))?;
+                value.set_sensitive(true);
+                Ok((name, value))
+            })
+            .collect::<Result<_, _>>()
+            .map(Headers)
+    }
+}
+
+impl HttpQuery {
+    /// an address is a few dozen bytes, even wrapped in json or a small html page
+    pub const DEFAULT_MAX_BODY_SIZE: u64 = 64 * 1024;
+
+    pub async fn resolve(&self, url: &Url, client: &RetryingClient) -> Result<Fetched, GetIpError> {
+        let mut request = client.request(self.method.0.clone(), url.clone());
+
+        for (name, value) in self.headers.0.iter() {
+            request = request.header(name.clone(), value.clone());
+        }
+
+        if let Some(BasicAuth { username, password }) = &self.basic_auth {
+            request = request.basic_auth(username, password.as_deref());
+        }
+
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout.0);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let redirected_to = Some(response.url().clone()).filter(|final_url| final_url != url);
+        let limit = self.max_body_size.unwrap_or(Self::DEFAULT_MAX_BODY_SIZE);
+
+        Ok(Fetched {
+            body: read_body(response, limit).await?,
+            status: Some(status),
+            headers,
+            redirected_to,
+        })
+    }
+}
+
+/// reads the body in chunks, and gives up as soon as it's larger than `limit` bytes
+pub async fn read_body(response: Response, limit: u64) -> Result<Bytes, GetIpError> {
+    let too_large = || GetIpError::BodyTooLarge { limit };
+
+    if response.content_length().is_some_and(|len| len > limit) {
+        return Err(too_large());
+    }
+
+    let mut body = BytesMut::new();
+    let mut chunks = pin!(response.bytes_stream());
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body.freeze())
+}
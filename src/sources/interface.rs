@@ -0,0 +1,112 @@
+use crate::config::cidr::Cidr;
+use crate::config::ip_source::GetIpError;
+use bytes::Bytes;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// reads the addresses bound to a local interface, for hosts that have their public address
+/// on `eth0` or `ppp0`
+///
+/// ```toml
+/// [wan]
+/// interface = "ppp0"
+/// prefix = "203.0.113.0/24"
+/// ```
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq, Deserialize)]
+pub struct InterfaceQuery {
+    interface: Box<str>,
+    /// skip private, loopback, link local and shared (cgnat) addresses
+    #[serde(default = "InterfaceQuery::default_global")]
+    global: bool,
+    /// only addresses within this block
+    #[serde(default, deserialize_with = "ipv4_prefix")]
+    prefix: Option<Cidr>,
+}
+
+/// an ipv6 prefix would never match, we only ever look at ipv4 addresses
+fn ipv4_prefix<'de, D>(deserializer: D) -> Result<Option<Cidr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let prefix = Cidr::deserialize(deserializer)?;
+    match prefix.is_ipv4() {
+        true => Ok(Some(prefix)),
+        false => Err(D::Error::custom(format_args!(
+            "prefix {prefix} isn't an ipv4 block, only ipv4 addresses can be published"
+        ))),
+    }
+}
+
+fn is_global(ip: Ipv4Addr) -> bool {
+    let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || shared)
+}
+
+impl InterfaceQuery {
+    const fn default_global() -> bool {
+        true
+    }
+
+    fn matches(&self, ip: Ipv4Addr) -> bool {
+        (!self.global || is_global(ip))
+            && self
+                .prefix
+                .is_none_or(|prefix| prefix.contains(IpAddr::V4(ip)))
+    }
+
+    /// the first matching address
+    pub async fn resolve(&self) -> Result<Bytes, GetIpError> {
+        let addresses = addresses(&self.interface)?.ok_or_else(|| {
+            GetIpError::Interface(format!("there is no interface `{}`", self.interface).into())
+        })?;
+
+        match addresses.into_iter().find(|ip| self.matches(*ip)) {
+            Some(ip) => Ok(ip.to_string().into()),
+            None => Err(GetIpError::Interface(
+                format!(
+                    "`{}` has no address that passes the filters",
+                    self.interface
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+/// the ipv4 addresses of `interface`, or `None` if there is no such interface
+#[cfg(unix)]
+fn addresses(interface: &str) -> io::Result<Option<Vec<Ipv4Addr>>> {
+    let mut found = false;
+    let mut addresses = vec![];
+    for ifaddr in nix::ifaddrs::getifaddrs()? {
+        if ifaddr.interface_name != interface {
+            continue;
+        }
+        found = true;
+
+        if let Some(v4) = ifaddr
+            .address
+            .as_ref()
+            .and_then(|addr| addr.as_sockaddr_in())
+        {
+            addresses.push(v4.ip());
+        }
+    }
+
+    Ok(found.then_some(addresses))
+}
+
+#[cfg(not(unix))]
+fn addresses(_: &str) -> io::Result<Option<Vec<Ipv4Addr>>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "interface sources are not supported on this platform",
+    ))
+}
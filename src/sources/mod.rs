@@ -0,0 +1,247 @@
+use crate::config::ip_source::GetIpError;
+use crate::config::Config;
+use crate::retrying_client::RetryingClient;
+use anyhow::Result;
+use bytes::Bytes;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::UdpSocket;
+use url::Url;
+
+mod dns;
+mod exec;
+mod file;
+mod http;
+mod interface;
+mod natpmp;
+mod stun;
+mod upnp;
+
+/// how an ip source is queried, picked from the scheme of its url,
+/// every kind produces bytes that go through the same process steps
+pub enum SourceKind {
+    Http(http::HttpQuery),
+    Dns(dns::DnsQuery),
+    Stun(stun::StunQuery),
+    Upnp(upnp::UpnpQuery),
+    Gateway(natpmp::GatewayQuery),
+    Local(LocalSource),
+}
+
+/// sources configured through their fields rather than a url,
+/// they are compared on reload since the url doesn't describe them
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq)]
+pub enum LocalSource {
+    Interface(interface::InterfaceQuery),
+    Exec(exec::ExecQuery),
+    File(file::FileQuery),
+}
+
+impl SourceKind {
+    /// rejects unknown schemes and malformed urls when `sources.toml` is loaded
+    pub fn parse(url: &Url) -> Result<Self> {
+        match url.scheme() {
+            "http" | "https" => Ok(SourceKind::Http(http::HttpQuery::default())),
+            "dns" => dns::DnsQuery::parse(url).map(SourceKind::Dns),
+            "stun" => stun::StunQuery::parse(url).map(SourceKind::Stun),
+            "upnp" => upnp::UpnpQuery::parse(url).map(SourceKind::Upnp),
+            "natpmp" => natpmp::GatewayQuery::parse_natpmp(url).map(SourceKind::Gateway),
+            "pcp" => natpmp::GatewayQuery::parse_pcp(url).map(SourceKind::Gateway),
+            scheme => anyhow::bail!("unsupported ip source `{url}`, unknown scheme `{scheme}`"),
+        }
+    }
+
+    /// like [`SourceKind::parse`], but http sources also take their request options from `table`
+    pub fn parse_table(url: &Url, table: &toml::Value) -> Result<Self> {
+        match Self::parse(url)? {
+            SourceKind::Http(_) => {
+                // `steps` and `tier` apply to every source, anything else has to be an http option
+                let mut table = table.clone();
+                if let Some(table) = table.as_table_mut() {
+                    table.remove("steps");
+                    table.remove("tier");
+                }
+                Ok(SourceKind::Http(table.try_into()?))
+            }
+            kind => {
+                let options = [
+                    "method",
+                    "headers",
+                    "timeout",
+                    "basic-auth",
+                    "basic_auth",
+                    "max-body-size",
+                    "max_body_size",
+                ];
+                if let Some(option) = options.into_iter().find(|x| table.get(x).is_some()) {
+                    anyhow::bail!("`{option}` only applies to http sources, not {url}")
+                }
+                Ok(kind)
+            }
+        }
+    }
+
+    /// a source table that configures one of the [`LocalSource`]s, the table key
+    /// is only a name for the source, e.g. `source:wan` for `[wan]`
+    pub fn from_table(name: &str, table: &toml::Value) -> Result<Option<(Url, Self)>> {
+        let kinds = ["interface", "exec", "file"];
+        let mut present = kinds.into_iter().filter(|kind| table.get(kind).is_some());
+
+        let local = match (present.next(), present.next()) {
+            (None, _) => return Ok(None),
+            (Some(a), Some(b)) => {
+                anyhow::bail!("[{name}] can't be both an `{a}` and an `{b}` source")
+            }
+            (Some("interface"), None) => {
+                // only A records are published, so there is no ipv6 address to filter for
+                let v6_options = ["ipv6", "temporary", "deprecated"];
+                if let Some(option) = v6_options.into_iter().find(|x| table.get(x).is_some()) {
+                    anyhow::bail!(
+                        "[{name}] `{option}` isn't supported, only ipv4 addresses can be published"
+                    )
+                }
+                LocalSource::Interface(table.clone().try_into()?)
+            }
+            (Some("exec"), None) => LocalSource::Exec(table.clone().try_into()?),
+            (Some(_), None) => LocalSource::File(table.clone().try_into()?),
+        };
+
+        let url = Url::parse(&format!("source:{name}"))?;
+        Ok(Some((url, SourceKind::Local(local))))
+    }
+
+    pub fn local(&self) -> Option<&LocalSource> {
+        match self {
+            SourceKind::Local(local) => Some(local),
+            _ => None,
+        }
+    }
+
+    pub fn http(&self) -> Option<&http::HttpQuery> {
+        match self {
+            SourceKind::Http(query) => Some(query),
+            _ => None,
+        }
+    }
+
+    pub async fn fetch(
+        &self,
+        url: &Url,
+        client: &RetryingClient,
+        cfg: &Config,
+    ) -> Result<Fetched, GetIpError> {
+        let body = match self {
+            SourceKind::Http(query) => return query.resolve(url, client).await,
+            SourceKind::Dns(query) => query.resolve(cfg).await,
+            SourceKind::Stun(query) => query.resolve(cfg).await,
+            SourceKind::Upnp(query) => query.resolve(client, cfg).await,
+            SourceKind::Gateway(query) => query.resolve(cfg).await,
+            SourceKind::Local(LocalSource::Interface(query)) => query.resolve().await,
+            SourceKind::Local(LocalSource::Exec(query)) => query.resolve(cfg).await,
+            SourceKind::Local(LocalSource::File(query)) => query.resolve().await,
+        };
+        body.map(Fetched::from)
+    }
+}
+
+/// what a source answered with, only http sources have a status and headers
+#[derive(Default)]
+pub struct Fetched {
+    pub body: Bytes,
+    pub status: Option<StatusCode>,
+    pub headers: HeaderMap,
+    /// where the last redirect pointed to, if any were followed
+    pub redirected_to: Option<Url>,
+}
+
+impl From<Bytes> for Fetched {
+    fn from(body: Bytes) -> Self {
+        Fetched {
+            body,
+            ..Fetched::default()
+        }
+    }
+}
+
+/// random bytes for ids and nonces, so spoofed answers are less likely to be taken
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    if ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut bytes).is_err() {
+        let fallback = std::process::id().to_ne_bytes();
+        bytes.iter_mut().zip(fallback).for_each(|(b, f)| *b = f);
+    }
+    bytes
+}
+
+/// how long to wait for an answer before sending the request again
+const RESEND_AFTER: Duration = Duration::from_secs(2);
+
+/// resolves `host:port` to an ipv4 address, we are after our ipv4 address,
+/// so the server has to see us over ipv4
+async fn resolve_v4(host: &str, port: u16) -> io::Result<SocketAddr> {
+    tokio::net::lookup_host((host, port))
+        .await?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{host} has no ipv4 address"),
+            )
+        })
+}
+
+async fn connect_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+/// sends a datagram and waits for the matching response, resending it every [`RESEND_AFTER`]
+/// until `timeout` runs out, datagrams that aren't a response to our request are ignored
+async fn udp_exchange(
+    socket: &UdpSocket,
+    request: &[u8],
+    timeout: Duration,
+    is_response: impl Fn(&[u8]) -> bool,
+) -> io::Result<Vec<u8>> {
+    let exchange = async {
+        let mut buf = vec![0; 2048];
+        loop {
+            socket.send(request).await?;
+
+            let recv = async {
+                loop {
+                    let len = socket.recv(&mut buf).await?;
+                    if is_response(&buf[..len]) {
+                        return io::Result::Ok(len);
+                    }
+                }
+            };
+
+            if let Ok(len) = tokio::time::timeout(RESEND_AFTER, recv).await {
+                buf.truncate(len?);
+                return Ok(buf);
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} didn't answer", socket.peer_addr()?),
+            ))
+        })
+}
+
+/// reads everything up to `limit` bytes, `None` if there was more than that
+async fn read_capped(reader: impl AsyncRead + Unpin, limit: u64) -> io::Result<Option<Bytes>> {
+    let mut buf = vec![];
+    reader.take(limit + 1).read_to_end(&mut buf).await?;
+    Ok((buf.len() as u64 <= limit).then(|| buf.into()))
+}
@@ -0,0 +1,248 @@
+use crate::config::ip_source::GetIpError;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use url::Url;
+
+const GATEWAY_PORT: u16 = 5351;
+
+const NATPMP_VERSION: u8 = 0;
+const NATPMP_EXTERNAL_ADDRESS: u8 = 0;
+
+const PCP_VERSION: u8 = 2;
+const PCP_MAP: u8 = 1;
+const PCP_RESPONSE: u8 = 0x80;
+const UDP: u8 = 17;
+/// the mapping is only made to learn the external address, so keep it short lived
+const PCP_LIFETIME: u32 = 30;
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    NatPmp,
+    Pcp,
+}
+
+/// `natpmp://gateway[:port]` or `pcp://gateway[:port]`,
+/// asks the router itself which address it has on the wan side
+pub struct GatewayQuery {
+    protocol: Protocol,
+    gateway: Box<str>,
+    port: u16,
+}
+
+impl GatewayQuery {
+    fn parse(url: &Url, protocol: Protocol) -> Result<Self> {
+        let gateway = url
+            .host_str()
+            .with_context(|| format!("{url} is missing the address of the router"))?;
+
+        anyhow::ensure!(
+            matches!(url.path(), "" | "/") && url.query().is_none(),
+            "{url} can only have a host and a port"
+        );
+
+        Ok(GatewayQuery {
+            protocol,
+            gateway: gateway.trim_matches(['[', ']']).into(),
+            port: url.port().unwrap_or(GATEWAY_PORT),
+        })
+    }
+
+    pub fn parse_natpmp(url: &Url) -> Result<Self> {
+        Self::parse(url, Protocol::NatPmp)
+    }
+
+    pub fn parse_pcp(url: &Url) -> Result<Self> {
+        Self::parse(url, Protocol::Pcp)
+    }
+
+    fn err(&self, msg: impl std::fmt::Display) -> GetIpError {
+        GetIpError::Gateway(format!("{}: {msg}", self.gateway).into())
+    }
+
+    /// the external address as text
+    pub async fn resolve(&self, cfg: &Config) -> Result<Bytes, GetIpError> {
+        let addr = super::resolve_v4(&self.gateway, self.port).await?;
+        let socket = super::connect_udp(addr).await?;
+        let timeout = cfg.http().client().timeout();
+
+        let ip = match self.protocol {
+            Protocol::NatPmp => {
+                let request = [NATPMP_VERSION, NATPMP_EXTERNAL_ADDRESS];
+                let response = super::udp_exchange(&socket, &request, timeout, |x| {
+                    matches!(x, [NATPMP_VERSION, 0x80, ..])
+                })
+                .await?;
+                self.natpmp_address(&response)?
+            }
+            Protocol::Pcp => {
+                let local = socket.local_addr()?;
+                let nonce = super::random::<12>();
+                let request = pcp_map_request(local, nonce);
+                let response = super::udp_exchange(&socket, &request, timeout, |x| {
+                    matches!(x, [PCP_VERSION, op, ..] if *op == PCP_RESPONSE | PCP_MAP)
+                        && x.get(24..36) == Some(&nonce)
+                })
+                .await?;
+                self.pcp_address(&response)?
+            }
+        };
+
+        Ok(ip.to_string().into())
+    }
+
+    fn natpmp_address(&self, response: &[u8]) -> Result<Ipv4Addr, GetIpError> {
+        match response {
+            [_, _, 0, 0, _, _, _, _, a, b, c, d, ..] => Ok(Ipv4Addr::new(*a, *b, *c, *d)),
+            [_, _, r0, r1, ..] => Err(self.err(format_args!(
+                "nat-pmp result code {}",
+                u16::from_be_bytes([*r0, *r1])
+            ))),
+            _ => Err(self.err("truncated nat-pmp response")),
+        }
+    }
+
+    fn pcp_address(&self, response: &[u8]) -> Result<Ipv4Addr, GetIpError> {
+        let result = *response
+            .get(3)
+            .ok_or_else(|| self.err("truncated pcp response"))?;
+        if result != 0 {
+            return Err(self.err(format_args!("pcp result code {result}")));
+        }
+
+        let external: [u8; 16] = response
+            .get(44..60)
+            .and_then(|x| x.try_into().ok())
+            .ok_or_else(|| self.err("truncated pcp response"))?;
+
+        std::net::Ipv6Addr::from(external)
+            .to_ipv4_mapped()
+            .ok_or_else(|| self.err("the router has no ipv4 address"))
+    }
+}
+
+/// a short lived udp mapping for our own socket, the response carries the external address
+fn pcp_map_request(local: SocketAddr, nonce: [u8; 12]) -> Vec<u8> {
+    let client = match local.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+
+    let mut request = Vec::with_capacity(60);
+    request.extend_from_slice(&[PCP_VERSION, PCP_MAP, 0, 0]);
+    request.extend_from_slice(&PCP_LIFETIME.to_be_bytes());
+    request.extend_from_slice(&client.octets());
+    request.extend_from_slice(&nonce);
+    request.extend_from_slice(&[UDP, 0, 0, 0]);
+    request.extend_from_slice(&local.port().to_be_bytes());
+    // no preference for the external port or address
+    request.extend_from_slice(&0_u16.to_be_bytes());
+    request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(url: &str) -> GatewayQuery {
+        let url = Url::parse(url).unwrap();
+        match url.scheme() {
+            "pcp" => GatewayQuery::parse_pcp(&url),
+            _ => GatewayQuery::parse_natpmp(&url),
+        }
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_urls() {
+        let natpmp = query("natpmp://192.168.1.1");
+        assert!(matches!(natpmp.protocol, Protocol::NatPmp));
+        assert_eq!(
+            (&*natpmp.gateway, natpmp.port),
+            ("192.168.1.1", GATEWAY_PORT)
+        );
+
+        let pcp = query("pcp://[fe80::1]:5350/");
+        assert!(matches!(pcp.protocol, Protocol::Pcp));
+        assert_eq!((&*pcp.gateway, pcp.port), ("fe80::1", 5350));
+
+        let err = GatewayQuery::parse_natpmp(&Url::parse("natpmp://router/ip").unwrap());
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn reads_natpmp_responses() {
+        let query = query("natpmp://192.168.1.1");
+        let response = [0, 0x80, 0, 0, 0, 0, 0x12, 0x34, 203, 0, 113, 7];
+        assert_eq!(
+            query.natpmp_address(&response).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+
+        // 3 is "network failure", the router has no address on the wan side yet
+        let err = query
+            .natpmp_address(&[0, 0x80, 0, 3, 0, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "192.168.1.1: nat-pmp result code 3");
+
+        let err = query.natpmp_address(&[0, 0x80]).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{err}");
+    }
+
+    #[test]
+    fn builds_pcp_requests() {
+        let local = SocketAddr::from(([192, 168, 1, 20], 40000));
+        let request = pcp_map_request(local, [7; 12]);
+
+        assert_eq!(request.len(), 60);
+        assert_eq!(request[..4], [PCP_VERSION, PCP_MAP, 0, 0]);
+        assert_eq!(request[4..8], PCP_LIFETIME.to_be_bytes());
+        assert_eq!(
+            request[8..24],
+            Ipv4Addr::new(192, 168, 1, 20).to_ipv6_mapped().octets()
+        );
+        assert_eq!(request[24..36], [7; 12]);
+        assert_eq!(request[36], UDP);
+        assert_eq!(request[40..42], 40000_u16.to_be_bytes());
+        assert_eq!(request[42..44], [0, 0]);
+        assert_eq!(
+            request[44..],
+            Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets()
+        );
+    }
+
+    #[test]
+    fn reads_pcp_responses() {
+        let query = query("pcp://192.168.1.1");
+        // the response mirrors the request, with the assigned address in place of ours
+        let mut response = pcp_map_request(SocketAddr::from(([192, 168, 1, 20], 40000)), [7; 12]);
+        response[1] = PCP_RESPONSE | PCP_MAP;
+        response[44..].copy_from_slice(&Ipv4Addr::new(203, 0, 113, 7).to_ipv6_mapped().octets());
+        assert_eq!(
+            query.pcp_address(&response).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+
+        let mut v6 = response.clone();
+        v6[44..].copy_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        let err = query.pcp_address(&v6).unwrap_err();
+        assert!(err.to_string().contains("no ipv4 address"), "{err}");
+
+        let mut failed = response.clone();
+        failed[3] = 8;
+        let err = query.pcp_address(&failed).unwrap_err();
+        assert_eq!(err.to_string(), "192.168.1.1: pcp result code 8");
+
+        for truncated in [&response[..3], &response[..59]] {
+            let err = query.pcp_address(truncated).unwrap_err();
+            assert!(err.to_string().contains("truncated pcp response"), "{err}");
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use crate::config::ip_source::GetIpError;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::net::Ipv4Addr;
+use url::Url;
+
+const STUN_PORT: u16 = 3478;
+const MAGIC_COOKIE: [u8; 4] = 0x2112A442_u32.to_be_bytes();
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+
+const MAPPED_ADDRESS: u16 = 0x0001;
+const ERROR_CODE: u16 = 0x0009;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+const FAMILY_V4: u8 = 0x01;
+
+/// `stun://host[:port]`, sends an rfc 5389 binding request,
+/// the server answers with the address and port it saw us from
+pub struct StunQuery {
+    host: Box<str>,
+    port: u16,
+}
+
+impl StunQuery {
+    pub fn parse(url: &Url) -> Result<Self> {
+        let host = url
+            .host_str()
+            .with_context(|| format!("{url} is missing the stun server"))?;
+
+        anyhow::ensure!(
+            matches!(url.path(), "" | "/") && url.query().is_none(),
+            "{url} can only have a host and a port"
+        );
+
+        Ok(StunQuery {
+            host: host.trim_matches(['[', ']']).into(),
+            port: url.port().unwrap_or(STUN_PORT),
+        })
+    }
+
+    /// the mapped address as text
+    pub async fn resolve(&self, cfg: &Config) -> Result<Bytes, GetIpError> {
+        let addr = super::resolve_v4(&self.host, self.port).await?;
+
+        let transaction_id = super::random::<12>();
+        let mut request = Vec::with_capacity(20);
+        request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+        request.extend_from_slice(&0_u16.to_be_bytes());
+        request.extend_from_slice(&MAGIC_COOKIE);
+        request.extend_from_slice(&transaction_id);
+
+        let socket = super::connect_udp(addr).await?;
+        let response = super::udp_exchange(&socket, &request, cfg.http().client().timeout(), |x| {
+            x.len() >= 20 && x[4..8] == MAGIC_COOKIE && x[8..20] == transaction_id
+        })
+        .await?;
+
+        let ip = self.mapped_address(&response)?;
+        Ok(ip.to_string().into())
+    }
+
+    fn mapped_address(&self, response: &[u8]) -> Result<Ipv4Addr, GetIpError> {
+        let err = |msg: &str| GetIpError::Stun(format!("{}: {msg}", self.host).into());
+
+        let kind = u16::from_be_bytes([response[0], response[1]]);
+        let len = u16::from_be_bytes([response[2], response[3]]) as usize;
+        let mut attrs = response
+            .get(20..20 + len)
+            .ok_or_else(|| err("truncated response"))?;
+
+        let mut mapped = None;
+        while let [t0, t1, l0, l1, rest @ ..] = attrs {
+            let attr = u16::from_be_bytes([*t0, *t1]);
+            let attr_len = u16::from_be_bytes([*l0, *l1]) as usize;
+            let value = rest
+                .get(..attr_len)
+                .ok_or_else(|| err("truncated attribute"))?;
+
+            match (attr, value) {
+                (ERROR_CODE, [_, _, class, number, reason @ ..]) => {
+                    let code = (*class & 0x07) as u16 * 100 + *number as u16;
+                    let reason = String::from_utf8_lossy(reason);
+                    return Err(err(&format!("error {code} {reason}")));
+                }
+                (XOR_MAPPED_ADDRESS, [_, FAMILY_V4, _, _, a, b, c, d]) => {
+                    let [m0, m1, m2, m3] = MAGIC_COOKIE;
+                    return Ok(Ipv4Addr::new(a ^ m0, b ^ m1, c ^ m2, d ^ m3));
+                }
+                // servers that only speak rfc 3489
+                (MAPPED_ADDRESS, [_, FAMILY_V4, _, _, a, b, c, d]) => {
+                    mapped = Some(Ipv4Addr::new(*a, *b, *c, *d))
+                }
+                _ => {}
+            }
+
+            // attributes are padded to 4 bytes
+            let padded = attr_len.next_multiple_of(4);
+            attrs = rest.get(padded..).unwrap_or_default();
+        }
+
+        match kind {
+            BINDING_SUCCESS => mapped.ok_or_else(|| err("no ipv4 mapped address in the response")),
+            BINDING_ERROR => Err(err("binding request failed")),
+            _ => Err(err(&format!("unexpected message type {kind:#06x}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query() -> StunQuery {
+        StunQuery::parse(&Url::parse("stun://stun.example.com").unwrap()).unwrap()
+    }
+
+    /// a response with the given type and attributes, padding each attribute to 4 bytes
+    fn message(kind: u16, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = vec![];
+        for (attr, value) in attrs {
+            body.extend_from_slice(&attr.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+            body.resize(body.len().next_multiple_of(4), 0);
+        }
+
+        let mut response = vec![];
+        response.extend_from_slice(&kind.to_be_bytes());
+        response.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        response.extend_from_slice(&MAGIC_COOKIE);
+        response.extend_from_slice(&[0; 12]);
+        response.extend_from_slice(&body);
+        response
+    }
+
+    #[test]
+    fn parses_urls() {
+        let query = query();
+        assert_eq!((&*query.host, query.port), ("stun.example.com", STUN_PORT));
+
+        let query = StunQuery::parse(&Url::parse("stun://[::1]:19302/").unwrap()).unwrap();
+        assert_eq!((&*query.host, query.port), ("::1", 19302));
+
+        for url in [
+            "stun://stun.example.com/path",
+            "stun://stun.example.com?a=b",
+        ] {
+            let err = StunQuery::parse(&Url::parse(url).unwrap()).err().unwrap();
+            assert!(err.to_string().contains("can only have a host and a port"));
+        }
+    }
+
+    #[test]
+    fn reads_the_xor_mapped_address() {
+        let [m0, m1, m2, m3] = MAGIC_COOKIE;
+        let xored = [0, FAMILY_V4, 0x12, 0x34, 203 ^ m0, m1, 113 ^ m2, 7 ^ m3];
+        // an odd sized attribute in front, so the padding has to be skipped
+        let response = message(
+            BINDING_SUCCESS,
+            &[(0x8022, b"srv"), (XOR_MAPPED_ADDRESS, &xored)],
+        );
+        assert_eq!(
+            query().mapped_address(&response).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_mapped_address() {
+        let mapped = [0, FAMILY_V4, 0x12, 0x34, 198, 51, 100, 1];
+        let response = message(BINDING_SUCCESS, &[(MAPPED_ADDRESS, &mapped)]);
+        assert_eq!(
+            query().mapped_address(&response).unwrap(),
+            Ipv4Addr::new(198, 51, 100, 1)
+        );
+
+        // only an ipv6 address is no answer for us
+        let v6 = [[0, 0x02, 0x12, 0x34].as_slice(), &[0; 16]].concat();
+        let response = message(BINDING_SUCCESS, &[(XOR_MAPPED_ADDRESS, &v6)]);
+        let err = query().mapped_address(&response).unwrap_err();
+        assert!(err.to_string().contains("no ipv4 mapped address"), "{err}");
+    }
+
+    #[test]
+    fn reports_errors() {
+        let response = message(
+            BINDING_ERROR,
+            &[(ERROR_CODE, b"\0\0\x04\x14Unknown Attribute")],
+        );
+        let err = query().mapped_address(&response).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "stun.example.com: error 420 Unknown Attribute"
+        );
+
+        let err = query().mapped_address(&message(0x0112, &[])).unwrap_err();
+        assert!(
+            err.to_string().contains("unexpected message type 0x0112"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        let mut truncated = message(BINDING_SUCCESS, &[(MAPPED_ADDRESS, &[0; 8])]);
+        truncated.truncate(truncated.len() - 1);
+        let err = query().mapped_address(&truncated).unwrap_err();
+        assert!(err.to_string().contains("truncated response"), "{err}");
+
+        // the attribute claims more than the message holds
+        let mut overlong = message(BINDING_SUCCESS, &[(MAPPED_ADDRESS, &[0; 8])]);
+        overlong[22..24].copy_from_slice(&12_u16.to_be_bytes());
+        let err = query().mapped_address(&overlong).unwrap_err();
+        assert!(err.to_string().contains("truncated attribute"), "{err}");
+    }
+}
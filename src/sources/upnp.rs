@@ -0,0 +1,310 @@
+use super::http::{read_body, HttpQuery};
+use crate::config::ip_source::GetIpError;
+use crate::config::Config;
+use crate::retrying_client::RetryingClient;
+use anyhow::Result;
+use bytes::Bytes;
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use url::{Host, Url};
+
+const SSDP_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+const SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// `upnp://` discovers the internet gateway device over ssdp,
+/// `upnp://host:port/path` skips discovery and reads the device description from there
+pub struct UpnpQuery {
+    description: Option<Url>,
+}
+
+impl UpnpQuery {
+    pub fn parse(url: &Url) -> Result<Self> {
+        let description = match url.host_str() {
+            None | Some("") => None,
+            Some(host) => {
+                let mut description = Url::parse("http://localhost")?;
+                description
+                    .set_host(Some(host))
+                    .map_err(|e| anyhow::anyhow!("invalid host in {url}: {e}"))?;
+                _ = description.set_port(url.port());
+                description.set_path(url.path());
+                description.set_query(url.query());
+                Some(description)
+            }
+        };
+
+        Ok(UpnpQuery { description })
+    }
+
+    fn err(msg: impl std::fmt::Display) -> GetIpError {
+        GetIpError::Gateway(format!("upnp: {msg}").into())
+    }
+
+    /// the external address as text
+    pub async fn resolve(
+        &self,
+        client: &RetryingClient,
+        cfg: &Config,
+    ) -> Result<Bytes, GetIpError> {
+        let description = match &self.description {
+            Some(description) => description.clone(),
+            None => discover(cfg.http().client().timeout()).await?,
+        };
+
+        let response = client.get(description.clone()).send().await?;
+        let xml = read_xml(response).await?;
+        let (service, control) = find_wan_service(&xml).ok_or_else(|| {
+            Self::err(format_args!("{description} has no wan connection service"))
+        })?;
+
+        let base = tag(&xml, "URLBase")
+            .and_then(|base| Url::parse(base.trim()).ok())
+            .unwrap_or(description);
+        let control = base
+            .join(control.trim())
+            .map_err(|e| Self::err(format_args!("invalid control url `{control}`: {e}")))?;
+
+        let body = format!(
+            r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><u:GetExternalIPAddress xmlns:u="{service}"></u:GetExternalIPAddress></s:Body></s:Envelope>"#
+        );
+        let action = format!("\"{service}#GetExternalIPAddress\"");
+        let action = HeaderValue::from_str(&action).map_err(Self::err)?;
+
+        let response = client
+            .post(control)
+            .header(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/xml; charset=\"utf-8\""),
+            )
+            .header(HeaderName::from_static("soapaction"), action)
+            .body(body)
+            .send()
+            .await?;
+        let response = read_xml(response).await?;
+
+        tag(&response, "NewExternalIPAddress")
+            .map(|ip| Bytes::from(ip.trim().to_owned()))
+            .ok_or_else(|| Self::err("the router didn't report an external address"))
+    }
+}
+
+/// descriptions and soap responses are a few kilobytes, a router sending more is broken
+async fn read_xml(response: reqwest::Response) -> Result<String, GetIpError> {
+    let body = read_body(response, HttpQuery::DEFAULT_MAX_BODY_SIZE).await?;
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// the text between `<name>` and `</name>`, ignoring any namespace prefix and attributes
+fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    loop {
+        let start = rest.find('<')? + 1;
+        rest = &rest[start..];
+        let end = rest.find('>')?;
+        let open = rest[..end].split_whitespace().next().unwrap_or_default();
+        rest = &rest[end + 1..];
+
+        let local = open.rsplit(':').next().unwrap_or(open);
+        if local == name {
+            let close = rest.find("</")?;
+            return Some(&rest[..close]);
+        }
+    }
+}
+
+/// the service type and control url of the first wan connection service
+fn find_wan_service(xml: &str) -> Option<(&'static str, &str)> {
+    let services = xml
+        .split("<service>")
+        .skip(1)
+        .filter_map(|service| {
+            Some((
+                tag(service, "serviceType")?.trim(),
+                tag(service, "controlURL")?,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    WAN_SERVICES.into_iter().find_map(|wanted| {
+        services
+            .iter()
+            .find(|(kind, _)| *kind == wanted)
+            .map(|(_, control)| (wanted, *control))
+    })
+}
+
+/// whether `location` points back at the device that answered, on the local network,
+/// anyone else on the network could otherwise send us to an arbitrary url
+fn is_local_to(location: &Url, peer: IpAddr) -> bool {
+    let IpAddr::V4(peer) = peer else {
+        return false;
+    };
+    location.host() == Some(Host::Ipv4(peer)) && (peer.is_private() || peer.is_link_local())
+}
+
+/// multicasts an m-search and returns the location of the first gateway that answers
+async fn discover(timeout: Duration) -> Result<Url, GetIpError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {SSDP_ADDR}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {SEARCH_TARGET}\r\n\r\n"
+    );
+
+    let search = async {
+        let mut buf = [0; 2048];
+        loop {
+            socket.send_to(request.as_bytes(), SSDP_ADDR).await?;
+
+            let recv = async {
+                loop {
+                    let (len, peer) = socket.recv_from(&mut buf).await?;
+                    let response = String::from_utf8_lossy(&buf[..len]);
+                    let location = response.lines().find_map(|line| {
+                        let (key, value) = line.split_once(':')?;
+                        key.trim()
+                            .eq_ignore_ascii_case("location")
+                            .then(|| Url::parse(value.trim()).ok())?
+                    });
+
+                    match location {
+                        Some(location) if is_local_to(&location, peer.ip()) => {
+                            return Ok::<_, GetIpError>(location)
+                        }
+                        Some(location) => log::debug!(
+                            peer:% = peer,
+                            location:% = location;
+                            "ignoring an ssdp answer that points elsewhere"
+                        ),
+                        None => {}
+                    }
+                }
+            };
+
+            if let Ok(location) = tokio::time::timeout(super::RESEND_AFTER, recv).await {
+                return location;
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, search)
+        .await
+        .unwrap_or_else(|_| Err(UpnpQuery::err("no internet gateway device answered")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <URLBase>http://192.168.1.1:5000/</URLBase>
+  <device>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+        <controlURL>/ctl/PPP</controlURL>
+      </service>
+      <service>
+        <serviceType> urn:schemas-upnp-org:service:WANIPConnection:1 </serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+    #[test]
+    fn finds_tags() {
+        assert_eq!(tag("<a>1</a><b>2</b>", "b"), Some("2"));
+        assert_eq!(
+            tag(DESCRIPTION, "URLBase"),
+            Some("http://192.168.1.1:5000/")
+        );
+        // the first match wins
+        assert_eq!(tag(DESCRIPTION, "controlURL"), Some("/ctl/L3F"));
+        assert_eq!(tag("<a></a>", "a"), Some(""));
+        assert_eq!(tag("<a>1</a>", "b"), None);
+        // an unclosed tag has no text
+        assert_eq!(tag("<a>1", "a"), None);
+        assert_eq!(tag("<a", "a"), None);
+    }
+
+    #[test]
+    fn ignores_namespaces_and_attributes() {
+        let soap = r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>
+            <u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            <NewExternalIPAddress xmlns:dt="urn:schemas-microsoft-com:datatypes" dt:dt="string"> 203.0.113.7 </NewExternalIPAddress>
+            </u:GetExternalIPAddressResponse></s:Body></s:Envelope>"#;
+        assert_eq!(tag(soap, "NewExternalIPAddress"), Some(" 203.0.113.7 "));
+        assert!(tag(soap, "Body")
+            .unwrap()
+            .contains("GetExternalIPAddressResponse"));
+        // a tag that only starts with the name isn't it
+        assert_eq!(
+            tag(
+                "<NewExternalIPAddressX>1</NewExternalIPAddressX>",
+                "NewExternalIPAddress"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn prefers_the_newest_wan_service() {
+        assert_eq!(
+            find_wan_service(DESCRIPTION),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:1",
+                "/ctl/IPConn"
+            ))
+        );
+
+        let ppp_only = DESCRIPTION.replace("WANIPConnection", "WANIPv6FirewallControl");
+        assert_eq!(
+            find_wan_service(&ppp_only),
+            Some((
+                "urn:schemas-upnp-org:service:WANPPPConnection:1",
+                "/ctl/PPP"
+            ))
+        );
+
+        let none = ppp_only.replace("WANPPPConnection", "WANCommonInterfaceConfig");
+        assert_eq!(find_wan_service(&none), None);
+    }
+
+    #[test]
+    fn only_follows_local_locations() {
+        let location = Url::parse("http://192.168.1.1:5000/rootDesc.xml").unwrap();
+        assert!(is_local_to(&location, "192.168.1.1".parse().unwrap()));
+        assert!(!is_local_to(&location, "192.168.1.2".parse().unwrap()));
+        assert!(!is_local_to(&location, "fe80::1".parse().unwrap()));
+
+        let public = Url::parse("http://203.0.113.7/rootDesc.xml").unwrap();
+        assert!(!is_local_to(&public, "203.0.113.7".parse().unwrap()));
+        let name = Url::parse("http://router.lan/rootDesc.xml").unwrap();
+        assert!(!is_local_to(&name, "192.168.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_urls() {
+        let discover = UpnpQuery::parse(&Url::parse("upnp://").unwrap()).unwrap();
+        assert!(discover.description.is_none());
+
+        let direct =
+            UpnpQuery::parse(&Url::parse("upnp://192.168.1.1:5000/rootDesc.xml?a=b").unwrap())
+                .unwrap();
+        assert_eq!(
+            direct.description.unwrap().as_str(),
+            "http://192.168.1.1:5000/rootDesc.xml?a=b"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// what we last knew about the record, persisted so restarts don't cost an api call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordState {
+    pub zone_id: Box<str>,
+    pub record: Box<str>,
+    pub record_id: Box<str>,
+    /// the content that was last pushed to, or verified against cloudflare
+    pub content: Ipv4Addr,
+    /// unix timestamp of the last time we changed the record
+    pub updated_at: u64,
+    /// unix timestamp of the last time we checked the record against cloudflare
+    pub verified_at: u64,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+impl RecordState {
+    /// whether this state still describes the configured record
+    pub fn matches(&self, cfg: &Config) -> bool {
+        *self.zone_id == *cfg.zone().id() && *self.record == *cfg.zone().record()
+    }
+
+    /// whether the state is too old to be trusted without asking cloudflare
+    pub fn needs_verification(&self, cfg: &Config) -> bool {
+        let interval = cfg.misc().refresh().verify_interval().as_secs();
+        unix_now() >= self.verified_at.saturating_add(interval)
+    }
+}
+
+pub struct StateStore {
+    path: PathBuf,
+    state: Mutex<Option<Option<RecordState>>>,
+}
+
+impl StateStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        StateStore {
+            path: path.into(),
+            state: Mutex::new(None),
+        }
+    }
+
+    async fn read(path: &Path) -> Result<Option<RecordState>> {
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    /// locks the state, lazily loading it from disk on first use,
+    /// a corrupt state file is treated as no state
+    pub async fn lock(&self) -> StateGuard<'_> {
+        let mut guard = self.state.lock().await;
+        if guard.is_none() {
+            let state = Self::read(&self.path).await.unwrap_or_else(|e| {
+                log::warn!("discarding the state file {}: {e}", self.path.display());
+                None
+            });
+            *guard = Some(state);
+        }
+
+        StateGuard {
+            path: &self.path,
+            guard,
+        }
+    }
+}
+
+pub struct StateGuard<'a> {
+    path: &'a Path,
+    guard: MutexGuard<'a, Option<Option<RecordState>>>,
+}
+
+impl StateGuard<'_> {
+    pub fn get(&self) -> Option<&RecordState> {
+        self.guard.as_ref().and_then(Option::as_ref)
+    }
+
+    /// forgets the state, forcing the next run to verify with cloudflare
+    pub async fn invalidate(&mut self) -> Result<()> {
+        *self.guard = Some(None);
+        match tokio::fs::remove_file(self.path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("unable to remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn set(&mut self, state: RecordState) -> Result<()> {
+        if self.get() == Some(&state) {
+            return Ok(());
+        }
+
+        let data = serde_json::to_vec_pretty(&state)?;
+        *self.guard = Some(Some(state));
+
+        // write then rename so a crash never leaves a half written state
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, self.path)
+            .await
+            .with_context(|| format!("unable to save {}", self.path.display()))
+    }
+}
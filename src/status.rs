@@ -0,0 +1,175 @@
+use crate::config::Config;
+use crate::http_server::{Request, Response};
+use crate::source_stats::SourceStats;
+use crate::state::{unix_now, RecordState};
+use serde_json::json;
+use std::net::Ipv4Addr;
+use std::num::NonZeroU8;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// the update loop is considered stuck if it didn't tick in two intervals and this much
+const TICK_GRACE: Duration = Duration::from_secs(60);
+
+struct Inner {
+    started: Instant,
+    last_tick: Option<Instant>,
+    /// unix timestamp
+    next_run: Option<u64>,
+    ip: Option<Ipv4Addr>,
+    record: Option<RecordState>,
+    /// when, and the unix timestamp
+    last_sync: Option<(Instant, u64)>,
+    /// the message, and the unix timestamp
+    last_error: Option<(Box<str>, u64)>,
+}
+
+/// what the update loop is up to, shared with the status api
+pub struct Status(Mutex<Inner>);
+
+impl Default for Status {
+    fn default() -> Self {
+        Status(Mutex::new(Inner {
+            started: Instant::now(),
+            last_tick: None,
+            next_run: None,
+            ip: None,
+            record: None,
+            last_sync: None,
+            last_error: None,
+        }))
+    }
+}
+
+impl Status {
+    fn with<R>(&self, fun: impl FnOnce(&mut Inner) -> R) -> R {
+        fun(&mut self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// the update loop woke up, and will wake up again in `next_run`
+    pub fn ticked(&self, next_run: Duration) {
+        self.with(|inner| {
+            inner.last_tick = Some(Instant::now());
+            inner.next_run = Some(unix_now() + next_run.as_secs());
+        })
+    }
+
+    /// something asked for an update, so the next run is right now
+    pub fn run_now(&self) {
+        self.with(|inner| inner.next_run = Some(unix_now()))
+    }
+
+    pub fn detected(&self, ip: Ipv4Addr) {
+        self.with(|inner| inner.ip = Some(ip))
+    }
+
+    /// the record is known to point at our address, which also settles any earlier error
+    pub fn synced(&self, record: Option<RecordState>) {
+        self.with(|inner| {
+            inner.record = record;
+            inner.last_sync = Some((Instant::now(), unix_now()));
+            inner.last_error = None;
+        })
+    }
+
+    pub fn failed(&self, err: &anyhow::Error) {
+        self.with(|inner| inner.last_error = Some((err.to_string().into(), unix_now())))
+    }
+}
+
+/// serves `/healthz`, `/readyz` and `/status`
+pub struct StatusApi {
+    status: Arc<Status>,
+    source_stats: Arc<SourceStats>,
+    interval: Duration,
+    ready_within: NonZeroU8,
+}
+
+impl StatusApi {
+    pub fn new(status: Arc<Status>, source_stats: Arc<SourceStats>, cfg: &Config) -> Self {
+        StatusApi {
+            status,
+            source_stats,
+            interval: cfg.misc().refresh().interval(),
+            ready_within: cfg.misc().status().ready_within(),
+        }
+    }
+
+    /// the update loop is still ticking
+    fn healthy(&self, inner: &Inner) -> bool {
+        let last_tick = inner.last_tick.unwrap_or(inner.started);
+        last_tick.elapsed() <= self.interval * 2 + TICK_GRACE
+    }
+
+    /// the last sync is recent enough to trust the record
+    fn ready(&self, inner: &Inner) -> bool {
+        let within = self.interval * self.ready_within.get() as u32;
+        inner
+            .last_sync
+            .is_some_and(|(synced, _)| synced.elapsed() <= within)
+    }
+
+    /// the health of every ip source that was asked so far
+    fn sources(&self) -> serde_json::Value {
+        let now = tokio::time::Instant::now();
+        let sources = self
+            .source_stats
+            .snapshot()
+            .into_iter()
+            .map(|(url, stats)| {
+                json!({
+                    "url": url.as_str(),
+                    "successes": stats.successes,
+                    "failures": stats.failures,
+                    "wrong_answers": stats.wrong_answers,
+                    "latency_ms": stats.latency.map(|latency| latency.as_millis() as u64),
+                    "failure_rate": stats.failure_rate,
+                    "wrong_rate": stats.wrong_rate,
+                    "demoted": stats.is_demoted(),
+                    "disabled_for": stats
+                        .disabled_until
+                        .filter(|until| *until > now)
+                        .map(|until| (until - now).as_secs()),
+                })
+            });
+        sources.collect()
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        const TEXT: &str = "text/plain; charset=utf-8";
+        let check = |ok, yes, no| match ok {
+            true => Response::new(200, TEXT, yes),
+            false => Response::new(503, TEXT, no),
+        };
+
+        self.status.with(|inner| match &*request.path {
+            "/healthz" => check(
+                self.healthy(inner),
+                "ok\n",
+                "the update loop stopped ticking\n",
+            ),
+            "/readyz" => check(
+                self.ready(inner),
+                "ready\n",
+                "the record wasn't synced recently\n",
+            ),
+            "/status" => {
+                let body = json!({
+                    "healthy": self.healthy(inner),
+                    "ready": self.ready(inner),
+                    "ip": inner.ip,
+                    "record": inner.record,
+                    "last_sync": inner.last_sync.map(|(_, at)| at),
+                    "last_error": inner.last_error.as_ref().map(|(message, at)| json!({
+                        "message": message,
+                        "at": at,
+                    })),
+                    "next_run": inner.next_run,
+                    "sources": self.sources(),
+                });
+                Response::new(200, "application/json", body.to_string())
+            }
+            _ => Response::not_found(),
+        })
+    }
+}
@@ -0,0 +1,220 @@
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    /// path and query
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, val)| &**val)
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).expect("request body wasn't json")
+    }
+}
+
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        MockResponse {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        MockResponse::new(status, body.to_string()).header("content-type", "application/json")
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&MockRequest) -> MockResponse + Send + Sync;
+
+/// a tiny http/1.1 stand-in server for driving the daemon end to end,
+/// every connection serves exactly one request
+pub struct MockServer {
+    addr: std::net::SocketAddr,
+    tls: bool,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    connections: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start(
+        handler: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> Self {
+        Self::spawn(None, Arc::new(handler)).await
+    }
+
+    /// serves https as `localhost` with the given certificate chain, leaf first, and its pkcs8 key
+    pub async fn start_tls(
+        chain: Vec<CertificateDer<'static>>,
+        key: Vec<u8>,
+        handler: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> Self {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key));
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .unwrap();
+
+        Self::spawn(Some(TlsAcceptor::from(Arc::new(config))), Arc::new(handler)).await
+    }
+
+    async fn spawn(tls: Option<TlsAcceptor>, handler: Arc<Handler>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let task = tokio::spawn({
+            let requests = Arc::clone(&requests);
+            let connections = Arc::clone(&connections);
+            let tls = tls.clone();
+            async move {
+                loop {
+                    let Ok((stream, _)) = listener.accept().await else {
+                        return;
+                    };
+                    connections.fetch_add(1, Ordering::Relaxed);
+                    let handler = Arc::clone(&handler);
+                    let requests = Arc::clone(&requests);
+                    let tls = tls.clone();
+                    tokio::spawn(async move {
+                        match tls {
+                            Some(tls) => {
+                                let stream = tls.accept(stream).await.ok()?;
+                                serve(stream, &*handler, &requests).await
+                            }
+                            None => serve(stream, &*handler, &requests).await,
+                        }
+                    });
+                }
+            }
+        });
+
+        MockServer {
+            addr,
+            tls: tls.is_some(),
+            requests,
+            connections,
+            task,
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        match self.tls {
+            true => format!("https://localhost:{}{path}", self.addr.port()),
+            false => format!("http://{}{path}", self.addr),
+        }
+    }
+
+    pub fn addr(&self) -> std::net::SocketAddr {
+        self.addr
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// every accepted connection, including the ones whose tls handshake failed
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+async fn serve(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    handler: &Handler,
+    requests: &Mutex<Vec<MockRequest>>,
+) -> Option<()> {
+    let mut stream = BufReader::new(stream);
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let target = parts.next()?.to_owned();
+
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await.ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (key, val) = header.split_once(':')?;
+        headers.push((key.trim().to_owned(), val.trim().to_owned()));
+    }
+
+    let len = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .map_or(0, |(_, val)| val.parse::<usize>().unwrap());
+
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await.ok()?;
+
+    let req = MockRequest {
+        method,
+        target,
+        headers,
+        body,
+    };
+
+    let resp = handler(&req);
+    // record before responding so the client never observes a response to an unrecorded request
+    requests.lock().unwrap().push(req);
+
+    let mut head = format!(
+        "HTTP/1.1 {} MOCK\r\ncontent-length: {}\r\nconnection: close\r\n",
+        resp.status,
+        resp.body.len()
+    );
+    for (key, val) in &resp.headers {
+        head += &format!("{key}: {val}\r\n");
+    }
+    head += "\r\n";
+
+    let stream = stream.get_mut();
+    stream.write_all(head.as_bytes()).await.ok()?;
+    stream.write_all(&resp.body).await.ok()?;
+    stream.shutdown().await.ok()
+}
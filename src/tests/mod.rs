@@ -0,0 +1,1255 @@
+use crate::cloudflare::manage::NewRecord;
+use crate::cloudflare::{CloudflareError, Match, RecordQuery, RecordType};
+use crate::config::Config;
+use crate::tests::mock_server::{MockRequest, MockResponse, MockServer};
+use crate::DdnsContext;
+use serde_json::json;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+mod mock_server;
+mod sources;
+mod tls;
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+const RECORD: &str = "home.example.com";
+const RECORD_ID: &str = "372e67954025e0ba6aaa6d586b9e0b59";
+/// the documentation ranges stand in for public addresses,
+/// and the local stun server and interfaces see us from loopback
+const ALLOWED: [&str; 4] = [
+    "127.0.0.0/8",
+    "192.0.2.0/24",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+];
+
+/// a fresh state file for every test so they don't share a cache
+fn state_file() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!(
+        "cloudflare-ddns-test-{}-{id}.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn config_for(server: &MockServer) -> Config {
+    let sources = format!(
+        r#"
+        ["{ip}"]
+        steps = ["Plaintext"]
+        "#,
+        ip = server.url("/ip"),
+    );
+
+    config_with_sources(server, &sources).await
+}
+
+async fn config_with_sources(server: &MockServer, sources: &str) -> Config {
+    try_config_with_sources(server, sources).await.unwrap()
+}
+
+/// no retries keeps failing tests fast
+const HTTP: &str = r#"
+    [client]
+    max-retries = 0
+    retry-interval = 00:00:01
+    timeout = 00:00:05
+"#;
+
+async fn try_config_with_sources(server: &MockServer, sources: &str) -> anyhow::Result<Config> {
+    try_config(server, HTTP, sources).await
+}
+
+async fn try_config(server: &MockServer, http: &str, sources: &str) -> anyhow::Result<Config> {
+    let api = format!(
+        r#"
+        api-base-url = "{base}"
+
+        [account]
+        email = "user@example.com"
+        api-token = "token"
+
+        [zone]
+        id = "{ZONE_ID}"
+        record = "{RECORD}"
+        "#,
+        base = server.url("/client/v4"),
+    );
+
+    let misc = format!(
+        "[general]\nstate-file = {state:?}\n[refresh]\n[validation]\nallow = {ALLOWED:?}",
+        state = state_file().display().to_string()
+    );
+
+    Config::from_toml(&api, http, &misc, sources).await
+}
+
+/// stands in for both the cloudflare api and an ip echo service
+fn cloudflare(
+    record_ip: &'static str,
+    our_ip: &'static str,
+) -> impl Fn(&MockRequest) -> MockResponse {
+    move |req| {
+        let records = format!("/client/v4/zones/{ZONE_ID}/dns_records");
+        let record = format!("{records}/{RECORD_ID}");
+
+        match (&*req.method, &*req.target) {
+            ("GET", "/ip") => MockResponse::new(200, our_ip),
+            ("GET", target) if target.starts_with(&*records) => MockResponse::json(
+                200,
+                json!({
+                    "success": true,
+                    "errors": [],
+                    "messages": [],
+                    "result": [{
+                        "id": RECORD_ID,
+                        "type": "A",
+                        "name": RECORD,
+                        "content": record_ip,
+                        "proxied": false,
+                    }],
+                }),
+            ),
+            ("PATCH", target) if target == record => MockResponse::json(
+                200,
+                json!({
+                    "success": true,
+                    "errors": [],
+                    "messages": [],
+                    "result": {
+                        "id": RECORD_ID,
+                        "type": "A",
+                        "name": RECORD,
+                        "content": req.json()["content"],
+                    },
+                }),
+            ),
+            _ => MockResponse::new(404, "not found"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn updates_changed_record() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    assert!(ctx.run_ddns(cfg).await.unwrap());
+
+    let requests = server.requests();
+    let patch = requests
+        .iter()
+        .find(|req| req.method == "PATCH")
+        .expect("record wasn't updated");
+
+    assert_eq!(patch.header("authorization"), Some("Bearer token"));
+    assert_eq!(patch.header("x-auth-email"), Some("user@example.com"));
+    assert_eq!(patch.json()["content"], "203.0.113.7");
+    assert_eq!(patch.json()["name"], RECORD);
+}
+
+#[tokio::test]
+async fn skips_unchanged_record() {
+    let server = MockServer::start(cloudflare("203.0.113.7", "203.0.113.7")).await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    assert!(!ctx.run_ddns(cfg).await.unwrap());
+    assert!(server.requests().iter().all(|req| req.method == "GET"));
+}
+
+#[tokio::test]
+async fn caches_record_state() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    assert!(ctx.run_ddns(cfg.clone()).await.unwrap());
+    let api_calls = || {
+        server
+            .requests()
+            .iter()
+            .filter(|req| req.target.starts_with("/client/v4"))
+            .count()
+    };
+    assert_eq!(api_calls(), 2);
+
+    // the ip didn't change, so we shouldn't even ask cloudflare
+    assert!(!ctx.run_ddns(cfg.clone()).await.unwrap());
+    assert_eq!(api_calls(), 2);
+
+    // a new context has to pick the state back up from disk
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert!(!ctx.run_ddns(cfg.clone()).await.unwrap());
+    assert_eq!(api_calls(), 2);
+
+    let state = std::fs::read(cfg.misc().general().state_file()).unwrap();
+    let state = serde_json::from_slice::<serde_json::Value>(&state).unwrap();
+    assert_eq!(state["record_id"], RECORD_ID);
+    assert_eq!(state["content"], "203.0.113.7");
+}
+
+fn quorum_sources(server: &MockServer, agree: u8) -> String {
+    let mut sources = format!("quorum = {{ query = 3, agree = {agree} }}\n");
+    for path in ["/ip", "/ip/a", "/ip/b"] {
+        sources += &format!("[\"{}\"]\nsteps = [\"Plaintext\"]\n", server.url(path));
+    }
+    sources
+}
+
+#[tokio::test]
+async fn quorum_outvotes_bad_source() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip/a" => MockResponse::new(200, "203.0.113.7"),
+        "/ip/b" => MockResponse::new(200, "192.0.2.1"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+    let cfg = config_with_sources(&server, &quorum_sources(&server, 2)).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    assert!(ctx.run_ddns(cfg).await.unwrap());
+    let patch = server
+        .requests()
+        .into_iter()
+        .find(|req| req.method == "PATCH")
+        .unwrap();
+    assert_eq!(patch.json()["content"], "203.0.113.7");
+}
+
+#[tokio::test]
+async fn quorum_refuses_ties() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip/a" => MockResponse::new(200, "203.0.113.7"),
+        "/ip/b" | "/ip/c" => MockResponse::new(200, "203.0.113.9"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+    let mut sources = "quorum = { query = 4, agree = 2 }\n".to_owned();
+    for path in ["/ip", "/ip/a", "/ip/b", "/ip/c"] {
+        sources += &format!("[\"{}\"]\nsteps = [\"Plaintext\"]\n", server.url(path));
+    }
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let err = ctx.run_ddns(cfg).await.unwrap_err();
+    assert!(err.to_string().contains("same number of votes"), "{err}");
+    assert!(server.requests().iter().all(|req| req.method != "PATCH"));
+}
+
+#[tokio::test]
+async fn quorum_holds_back_update() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip/a" => MockResponse::new(200, "198.51.100.9"),
+        "/ip/b" => MockResponse::new(200, "192.0.2.1"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+    let cfg = config_with_sources(&server, &quorum_sources(&server, 2)).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let err = ctx.run_ddns(cfg).await.unwrap_err();
+    assert!(err.to_string().contains("no quorum"), "{err}");
+    assert!(err.to_string().contains("192.0.2.1"), "{err}");
+    assert!(server.requests().iter().all(|req| req.method != "PATCH"));
+}
+
+#[tokio::test]
+async fn reports_failed_update() {
+    let server = MockServer::start(|req| match &*req.method {
+        "PATCH" => MockResponse::json(
+            403,
+            json!({
+                "success": false,
+                "errors": [{ "code": 10000, "message": "Authentication error" }],
+                "messages": [],
+                "result": null,
+            }),
+        ),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let err = ctx.run_ddns(cfg).await.unwrap_err();
+    assert!(
+        err.to_string().contains("code 10000: Authentication error"),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn parses_error_envelope() {
+    let server = MockServer::start(|_| {
+        MockResponse::json(
+            400,
+            json!({
+                "success": false,
+                "errors": [{ "code": 81057, "message": "record already exists" }],
+                "messages": [],
+                "result": null,
+            }),
+        )
+    })
+    .await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let record = NewRecord {
+        kind: RecordType::A,
+        name: RECORD,
+        content: "203.0.113.7",
+        ttl: 1,
+        proxied: false,
+        comment: None,
+        tags: &[],
+    };
+
+    let err = ctx
+        .cloudflare(&cfg)
+        .create_record(ZONE_ID, &record)
+        .await
+        .unwrap_err();
+
+    assert!(err.has_code(81057));
+    assert!(
+        err.to_string()
+            .contains("code 81057: record already exists"),
+        "{err}"
+    );
+
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].json()["type"], "A");
+    assert!(requests[0].json().get("comment").is_none());
+}
+
+#[tokio::test]
+async fn walks_every_record_page() {
+    let server = MockServer::start(|req| {
+        let url = url::Url::parse(&format!("http://localhost{}", req.target)).unwrap();
+        let page = url
+            .query_pairs()
+            .find(|(key, _)| key == "page")
+            .map_or(1, |(_, page)| page.parse::<u32>().unwrap());
+
+        let records = (0..2)
+            .map(|i| {
+                json!({
+                    "id": format!("record-{page}-{i}"),
+                    "type": "A",
+                    "name": format!("{page}-{i}.example.com"),
+                    "content": "203.0.113.7",
+                })
+            })
+            .collect::<Vec<_>>();
+
+        MockResponse::json(
+            200,
+            json!({
+                "success": true,
+                "errors": [],
+                "messages": [],
+                "result": records,
+                "result_info": { "page": page, "per_page": 2, "count": 2, "total_count": 6, "total_pages": 3 },
+            }),
+        )
+    })
+    .await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let query = RecordQuery {
+        tags: &["ddns", "site:home"],
+        comment_contains: Some("managed"),
+        r#match: Match::Any,
+        per_page: Some(2),
+        ..RecordQuery::default()
+    };
+
+    let records = ctx
+        .cloudflare(&cfg)
+        .list_records(ZONE_ID, &query)
+        .await
+        .unwrap();
+
+    let ids = records.iter().map(|r| &*r.id).collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        [
+            "record-1-0",
+            "record-1-1",
+            "record-2-0",
+            "record-2-1",
+            "record-3-0",
+            "record-3-1"
+        ]
+    );
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    let query = requests[0].target.split_once('?').unwrap().1;
+    for param in [
+        "tag=ddns",
+        "tag=site%3Ahome",
+        "comment.contains=managed",
+        "match=any",
+        "per_page=2",
+    ] {
+        assert!(query.split('&').any(|p| p == param), "{param} in {query}");
+    }
+}
+
+#[tokio::test]
+async fn fails_when_the_page_is_ignored() {
+    // always answers with the first page, whatever was asked for
+    let server = MockServer::start(|_| {
+        MockResponse::json(
+            200,
+            json!({
+                "success": true,
+                "errors": [],
+                "messages": [],
+                "result": [{ "id": "record-1", "type": "A", "name": "example.com", "content": "203.0.113.7" }],
+                "result_info": { "page": 1, "per_page": 1, "count": 1, "total_count": 2, "total_pages": 2 },
+            }),
+        )
+    })
+    .await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let err = ctx
+        .cloudflare(&cfg)
+        .list_records(ZONE_ID, &RecordQuery::default())
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err,
+            CloudflareError::UnexpectedPage {
+                requested: 2,
+                received: 1
+            }
+        ),
+        "{err}"
+    );
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn gives_up_on_endless_pages() {
+    // echoes the page back, but never runs out of them
+    let server = MockServer::start(|req| {
+        let url = url::Url::parse(&format!("http://localhost{}", req.target)).unwrap();
+        let page = url
+            .query_pairs()
+            .find(|(key, _)| key == "page")
+            .map_or(1, |(_, page)| page.parse::<u32>().unwrap());
+
+        MockResponse::json(
+            200,
+            json!({
+                "success": true,
+                "errors": [],
+                "messages": [],
+                "result": [{ "id": format!("record-{page}"), "type": "A", "name": "example.com", "content": "203.0.113.7" }],
+                "result_info": { "page": page, "per_page": 1, "count": 1 },
+            }),
+        )
+    })
+    .await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let err = ctx
+        .cloudflare(&cfg)
+        .list_records(ZONE_ID, &RecordQuery::default())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, CloudflareError::TooManyPages(100)), "{err}");
+    assert_eq!(server.requests().len(), 100);
+}
+
+#[tokio::test]
+async fn deprioritizes_failing_source() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/down" => MockResponse::new(500, "internal server error"),
+        _ => cloudflare("203.0.113.7", "203.0.113.7")(req),
+    })
+    .await;
+    // "/down" sorts before "/ip", and one at a time keeps the order observable
+    let sources = format!(
+        "concurrent-resolve = 1\n[\"{down}\"]\nsteps = [\"Plaintext\"]\n[\"{ip}\"]\nsteps = [\"Plaintext\"]\n",
+        down = server.url("/down"),
+        ip = server.url("/ip"),
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let down_hits = || {
+        server
+            .requests()
+            .iter()
+            .filter(|req| req.target == "/down")
+            .count()
+    };
+
+    ctx.run_ddns(cfg.clone()).await.unwrap();
+    assert_eq!(down_hits(), 1);
+
+    // a source that never answered doesn't outrank one that did, the healthy source is asked first from now on
+    for _ in 0..2 {
+        ctx.run_ddns(cfg.clone()).await.unwrap();
+    }
+    assert_eq!(down_hits(), 1);
+    let stats = ctx.source_stats.to_string();
+    assert!(stats.contains("/down: 0 ok, 1 failed"), "{stats}");
+    assert!(stats.contains("/ip: 3 ok, 0 failed"), "{stats}");
+}
+
+#[tokio::test]
+async fn extracts_ip_with_regex() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip" => MockResponse::new(
+            200,
+            "<html><body>Current IP Address: 203.0.113.7</body></html>",
+        ),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+    let sources = format!(
+        "[\"{ip}\"]\nsteps = [{{ Regex = {{ pattern = 'Address: (?<ip>[\\d.]+)', group = \"ip\" }} }}]\n",
+        ip = server.url("/ip"),
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    assert!(ctx.run_ddns(cfg).await.unwrap());
+    let patch = server
+        .requests()
+        .into_iter()
+        .find(|req| req.method == "PATCH")
+        .unwrap();
+    assert_eq!(patch.json()["content"], "203.0.113.7");
+}
+
+#[tokio::test]
+async fn rejects_invalid_patterns() {
+    let server = MockServer::start(|_| MockResponse::new(404, "not found")).await;
+    for (steps, reason) in [
+        (
+            "[{ Regex = { pattern = 'Address: ([\\d.]+' } }]",
+            "regex parse error",
+        ),
+        (
+            "[{ Regex = { pattern = 'Address: ([\\d.]+)', group = 2 } }]",
+            "no capture group 2",
+        ),
+        (
+            "[{ Regex = { pattern = 'Address: ([\\d.]+)', group = \"ip\" } }]",
+            "no capture group `ip`",
+        ),
+    ] {
+        let sources = format!("[\"{}\"]\nsteps = {steps}\n", server.url("/ip"));
+        let err = try_config_with_sources(&server, &sources)
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("{steps} was accepted"));
+        assert!(format!("{err:#}").contains(reason), "{err:#}");
+    }
+}
+
+#[tokio::test]
+async fn extracts_nested_json() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/pointer" => MockResponse::json(
+            200,
+            json!({ "meta": { "ip": "10.0.0.1" }, "data": { "a/b": ["10.0.0.2", "203.0.113.7"] } }),
+        ),
+        "/dotted" => MockResponse::json(
+            200,
+            json!([{ "ips": [] }, { "ips": ["10.0.0.3", { "v4": "203.0.113.7" }] }]),
+        ),
+        "/missing" => MockResponse::json(200, json!({ "data": { "ipv6": "2001:db8::1" } })),
+        "/dotted-key" => MockResponse::json(
+            200,
+            json!({ "data": { "ip": "10.0.0.1" }, "data.ip": "203.0.113.7" }),
+        ),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let sources = format!(
+        r#"
+        quorum = {{ query = 2, agree = 2 }}
+        ["{pointer}"]
+        steps = [{{ Json = {{ key = "/data/a~1b/1" }} }}]
+        ["{dotted}"]
+        steps = [{{ Json = {{ key = "$[1].ips[1].v4" }} }}]
+        "#,
+        pointer = server.url("/pointer"),
+        dotted = server.url("/dotted"),
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        std::net::Ipv4Addr::new(203, 0, 113, 7)
+    );
+
+    let sources = format!(
+        "[\"{}\"]\nsteps = [{{ Json = {{ key = \"$.data.ip\" }} }}]\n",
+        server.url("/missing")
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    let err = ctx.get_ip(&cfg).await.unwrap_err();
+    assert!(
+        err.to_string().contains("missing field `$.data.ip`"),
+        "{err}"
+    );
+
+    // without `$.` a key is taken as is, dots and all
+    let sources = format!(
+        "[\"{}\"]\nsteps = [{{ Json = {{ key = \"data.ip\" }} }}]\n",
+        server.url("/dotted-key")
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        std::net::Ipv4Addr::new(203, 0, 113, 7)
+    );
+
+    for key in ["$.", "$.data.", "$.ips[x]", "$[0", "/a~2"] {
+        let sources = format!(
+            "[\"{}\"]\nsteps = [{{ Json = {{ key = \"{key}\" }} }}]\n",
+            server.url("/missing")
+        );
+        assert!(
+            try_config_with_sources(&server, &sources).await.is_err(),
+            "{key:?} was accepted"
+        );
+    }
+}
+
+#[tokio::test]
+async fn tolerates_messy_plaintext() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/crlf" => MockResponse::new(200, "\r\n  203.0.113.7 \r\nserved by edge-3\r\n"),
+        "/split" => MockResponse::new(200, "country=XX;ip=203.0.113.7;asn=64496"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let sources = format!(
+        r#"
+        quorum = {{ query = 2, agree = 2 }}
+        ["{crlf}"]
+        steps = ["Trim", {{ Line = {{ index = 0 }} }}, "Trim"]
+        ["{split}"]
+        steps = [{{ Split = {{ delimiter = ";", index = 1 }} }}, {{ Split = {{ delimiter = "ip=", index = 1 }} }}]
+        "#,
+        crlf = server.url("/crlf"),
+        split = server.url("/split"),
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        std::net::Ipv4Addr::new(203, 0, 113, 7)
+    );
+
+    let sources = format!(
+        "[\"{}\"]\nsteps = [{{ Line = {{ index = 5 }} }}]\n",
+        server.url("/crlf")
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    let err = ctx.get_ip(&cfg).await.unwrap_err();
+    assert!(err.to_string().contains("less than 6 parts"), "{err}");
+}
+
+#[tokio::test]
+async fn refuses_to_publish_bogons() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip/cgnat" => MockResponse::new(200, "100.64.1.1"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let sources = format!(
+        "[\"{}\"]\nsteps = [\"Plaintext\"]\n",
+        server.url("/ip/cgnat")
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    let err = ctx.run_ddns(cfg).await.unwrap_err();
+    assert!(err.to_string().contains("100.64.1.1"), "{err}");
+    assert!(err.to_string().contains("cgnat"), "{err}");
+
+    // the next source is asked instead
+    let sources = format!(
+        "[\"{}\"]\nsteps = [\"Plaintext\"]\n[\"{}\"]\nsteps = [\"Plaintext\"]\n",
+        server.url("/ip/cgnat"),
+        server.url("/ip")
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert!(ctx.run_ddns(cfg).await.unwrap());
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|req| req.method == "PATCH")
+            .count(),
+        1
+    );
+
+    let check = |misc: &str, ip: [u8; 4]| {
+        let cfg = toml::from_str::<crate::config::misc::ValidationConfig>(misc).unwrap();
+        cfg.check(ip.into())
+    };
+    assert!(check("", [100, 64, 1, 1]).is_err());
+    assert!(check("", [8, 8, 8, 8]).is_ok());
+    assert!(check("allow = [\"100.64.0.0/10\"]", [100, 64, 1, 1]).is_ok());
+    assert!(check("allow = [\"100.64.0.0/10\"]", [10, 0, 0, 1]).is_err());
+    let err = check(
+        "deny = [\"8.8.8.0/24\"]\nallow = [\"8.8.8.8\"]",
+        [8, 8, 8, 8],
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "refusing to publish 8.8.8.8, it is a denied address (8.8.8.0/24)"
+    );
+
+    for misc in [
+        "allow = [\"2001:db8::/32\"]",
+        "deny = [\"8.8.8.8\", \"::1\"]",
+    ] {
+        let err = toml::from_str::<crate::config::misc::ValidationConfig>(misc).unwrap_err();
+        assert!(err.to_string().contains("isn't an ipv4 block"), "{err}");
+    }
+}
+
+#[tokio::test]
+async fn customizes_source_requests() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip/private" => {
+            let authorized = req.method == "POST"
+                && req.header("x-api-key") == Some("secret")
+                && req.header("authorization") == Some("Basic dXNlcjpodW50ZXIy");
+            match authorized {
+                true => MockResponse::new(200, "203.0.113.7"),
+                false => MockResponse::new(401, "unauthorized"),
+            }
+        }
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let sources = format!(
+        r#"
+        ["{}"]
+        method = "post"
+        headers = {{ x-api-key = "secret" }}
+        basic-auth = {{ username = "user", password = "hunter2" }}
+        timeout = 00:00:02
+        "#,
+        server.url("/ip/private")
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert!(ctx.run_ddns(cfg).await.unwrap());
+
+    let invalid = [
+        r#"["https://example.com"]
+        method = "not a method""#,
+        r#"["https://example.com"]
+        headers = { "bad header" = "x" }"#,
+        r#"["dns://resolver1.opendns.com/myip.opendns.com"]
+        headers = { x-api-key = "secret" }"#,
+    ];
+    for sources in invalid {
+        assert!(try_config_with_sources(&server, sources).await.is_err());
+    }
+
+    // a typo must not quietly send the request without the option
+    for (option, typo) in [
+        ("basic_auht = { username = \"user\" }", "basic_auht"),
+        (
+            "basic-auth = { username = \"user\", pasword = \"x\" }",
+            "pasword",
+        ),
+        ("max-body-sise = 1024", "max-body-sise"),
+        ("timout = 00:00:02", "timout"),
+    ] {
+        let sources = format!(
+            "[\"{}\"]\ntier = 1\nsteps = [\"Trim\"]\n{option}",
+            server.url("/ip/private")
+        );
+        let err = try_config_with_sources(&server, &sources)
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("{option} was accepted"));
+        assert!(
+            format!("{err:#}").contains(&format!("unknown field `{typo}`")),
+            "{err:#}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn reads_headers_and_checks_status() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/echo" => MockResponse::new(200, "").header("x-client-ip", "203.0.113.7, 10.0.0.1"),
+        "/redirect" => MockResponse::new(302, "").header("location", "/landed/203.0.113.7"),
+        "/landed/203.0.113.7" => MockResponse::new(200, "welcome"),
+        "/maintenance" => MockResponse::new(503, "203.0.113.9"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let run = |source: String| {
+        let server = &server;
+        async move {
+            let cfg = config_with_sources(server, &source).await;
+            let ctx = DdnsContext::new(cfg.clone()).unwrap();
+            ctx.run_ddns(cfg).await
+        }
+    };
+
+    let echo = format!(
+        r#"["{}"]
+        steps = [{{ Status = {{}} }}, {{ Header = {{ name = "X-Client-IP" }} }}, {{ Split = {{ delimiter = ", ", index = 0 }} }}]"#,
+        server.url("/echo")
+    );
+    assert!(run(echo).await.unwrap());
+
+    let redirect = format!(
+        r#"["{}"]
+        steps = [{{ Header = {{ name = "location" }} }}, {{ Regex = {{ pattern = '/landed/([0-9.]+)$' }} }}]"#,
+        server.url("/redirect")
+    );
+    assert!(run(redirect).await.unwrap());
+
+    let missing = format!(
+        r#"["{}"]
+        steps = [{{ Header = {{ name = "x-real-ip" }} }}]"#,
+        server.url("/echo")
+    );
+    let err = run(missing).await.unwrap_err();
+    assert!(err.to_string().contains("no `x-real-ip` header"), "{err}");
+
+    let maintenance = format!(
+        r#"["{}"]
+        steps = [{{ Status = {{}} }}]"#,
+        server.url("/maintenance")
+    );
+    let err = run(maintenance).await.unwrap_err();
+    assert!(err.to_string().contains("503"), "{err}");
+
+    let allowed = format!(
+        r#"["{}"]
+        steps = [{{ Status = {{ allow = [503] }} }}]"#,
+        server.url("/maintenance")
+    );
+    assert!(run(allowed).await.unwrap());
+
+    for steps in [
+        r#"[{ Header = { name = "not a header" } }]"#,
+        r#"[{ Status = { allow = [42] } }]"#,
+    ] {
+        let sources = format!("[\"{}\"]\nsteps = {steps}", server.url("/echo"));
+        assert!(try_config_with_sources(&server, &sources).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn falls_back_through_tiers() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/router/up" => MockResponse::new(200, "203.0.113.7"),
+        "/router/down" => MockResponse::new(500, "internal server error"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let hits = |target: &str| {
+        server
+            .requests()
+            .iter()
+            .filter(|req| req.target == target)
+            .count()
+    };
+
+    let tiered = |router: &str| {
+        format!(
+            "[tiers.1]\nconcurrent-resolve = 1\n[\"{router}\"]\ntier = 1\nsteps = [\"Plaintext\"]\n[\"{ip}\"]\nsteps = [\"Plaintext\"]\n",
+            router = server.url(router),
+            ip = server.url("/ip"),
+        )
+    };
+
+    // the public fallback isn't asked while the first tier answers
+    let cfg = config_with_sources(&server, &tiered("/router/up")).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        Ipv4Addr::new(203, 0, 113, 7)
+    );
+    assert_eq!((hits("/router/up"), hits("/ip")), (1, 0));
+
+    let cfg = config_with_sources(&server, &tiered("/router/down")).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        Ipv4Addr::new(203, 0, 113, 7)
+    );
+    assert_eq!((hits("/router/down"), hits("/ip")), (1, 1));
+
+    let unused = format!(
+        "[tiers.2]\nconcurrent-resolve = 1\n[\"{}\"]\ntier = 1\n",
+        server.url("/ip")
+    );
+    let err = try_config_with_sources(&server, &unused).await.unwrap_err();
+    assert!(err.to_string().contains("tier = 2"), "{err}");
+}
+
+#[tokio::test]
+async fn limits_response_size() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = MockServer::start(|req| match &*req.target {
+        "/huge" => MockResponse::new(200, vec![b' '; 1024 * 1024]),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    // no content-length, the body only ends when the connection does
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endless = format!("http://{}/endless", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let head = "HTTP/1.1 200 OK\r\nconnection: close\r\n\r\n";
+                let _ = stream.write_all(head.as_bytes()).await;
+                while stream.write_all(&[b' '; 16 * 1024]).await.is_ok() {}
+            });
+        }
+    });
+
+    let get_ip = |source: String| {
+        let server = &server;
+        async move {
+            let cfg = config_with_sources(server, &source).await;
+            let ctx = DdnsContext::new(cfg.clone()).unwrap();
+            ctx.get_ip(&cfg).await
+        }
+    };
+
+    let err = get_ip(format!("[\"{}\"]", server.url("/huge")))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("65536 byte limit"), "{err}");
+
+    let err = get_ip(format!("[\"{endless}\"]\nmax-body-size = 100000"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("100000 byte limit"), "{err}");
+
+    let source = format!("[\"{}\"]\nsteps = [\"Trim\"]\n", server.url("/ip"));
+    let err = get_ip(format!("{source}max-body-size = 8"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("8 byte limit"), "{err}");
+    assert!(get_ip(format!("{source}max-body-size = 11")).await.is_ok());
+}
+
+#[tokio::test]
+async fn reports_every_source() {
+    use crate::config::ip_source::GetIpError;
+
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip/a" => MockResponse::new(200, "203.0.113.7"),
+        "/ip/json" => MockResponse::json(200, json!({ "address": "203.0.113.7" })),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let sources = format!(
+        "quorum = {{ query = 3, agree = 2 }}\n[\"{}\"]\n[\"{}\"]\n[\"{}\"]\nsteps = [{{ Json = {{ key = \"ip\" }} }}]\n",
+        server.url("/ip"),
+        server.url("/ip/a"),
+        server.url("/ip/json"),
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    let report = ctx.get_ip_report(&cfg).await;
+
+    assert_eq!(
+        *report.outcome.as_ref().unwrap(),
+        Ipv4Addr::new(203, 0, 113, 7)
+    );
+    assert_eq!(report.sources.len(), 3);
+    assert_eq!(report.winners().count(), 2);
+    let failed = report
+        .sources
+        .iter()
+        .find(|source| source.url.path() == "/ip/json")
+        .unwrap();
+    assert!(matches!(
+        failed.result,
+        Err(GetIpError::Step {
+            index: 0,
+            step: "Json",
+            ..
+        })
+    ));
+    assert!(report.to_string().starts_with("203.0.113.7 from "));
+
+    // without an answer every source's error is reported, not just the last one
+    let sources = format!(
+        "[\"{}\"]\nsteps = [{{ Json = {{ key = \"ip\" }} }}]\n[\"{}\"]\nsteps = [{{ Regex = {{ pattern = 'v4=(.+)' }} }}]\n",
+        server.url("/ip/json"),
+        server.url("/ip/a"),
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    let err = ctx.get_ip(&cfg).await.unwrap_err().to_string();
+    assert!(err.contains("none of the ip sources answered"), "{err}");
+    assert!(err.contains("steps[0] (Json) failed"), "{err}");
+    assert!(err.contains("steps[0] (Regex) failed"), "{err}");
+}
+
+#[tokio::test]
+async fn runs_script_steps() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/router" => MockResponse::json(
+            200,
+            json!({ "interfaces": [
+                { "name": "lan", "ipv4": "192.168.1.1" },
+                { "name": "wan", "ipv4": "203.0.113.7" },
+            ]}),
+        )
+        .header("x-wan", "wan"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let get_ip = |code: &str| {
+        let sources = format!(
+            "[\"{}\"]\nsteps = [{{ Script = {{ code = '''{code}''' }} }}]\n",
+            server.url("/router")
+        );
+        let server = &server;
+        async move {
+            let cfg = try_config_with_sources(server, &sources).await?;
+            let ctx = DdnsContext::new(cfg.clone())?;
+            ctx.get_ip(&cfg).await
+        }
+    };
+
+    let ip = get_ip(
+        r#"
+        if status != 200 { throw "router answered with " + status }
+        let wan = parse_json(body).interfaces.filter(|x| x.name == headers["x-wan"]);
+        wan[0].ipv4
+        "#,
+    )
+    .await
+    .unwrap();
+    assert_eq!(ip, Ipv4Addr::new(203, 0, 113, 7));
+
+    let err = get_ip("loop {}").await.unwrap_err().to_string();
+    assert!(err.contains("steps[0] (Script) failed"), "{err}");
+    assert!(err.contains("1000000 operations"), "{err}");
+
+    let err = get_ip("42").await.unwrap_err().to_string();
+    assert!(err.contains("returned a i64"), "{err}");
+
+    let err = get_ip("let x = ;").await.unwrap_err().to_string();
+    assert!(err.contains("invalid script"), "{err}");
+
+    let err = get_ip(r#"eval("\"203.0.113.9\"")"#)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("invalid script"), "{err}");
+
+    let err = get_ip("let x = 1;").await.unwrap_err().to_string();
+    assert!(err.contains("didn't return anything"), "{err}");
+
+    let ip = get_ip(r#"blob(1, 0x32) + "03.0.113.7".to_blob()"#)
+        .await
+        .unwrap();
+    assert_eq!(ip, Ipv4Addr::new(203, 0, 113, 7));
+
+    // scripts can't reach the file system through modules
+    let module = std::env::temp_dir().join(format!("cloudflare-ddns-{}", std::process::id()));
+    std::fs::write(
+        module.with_extension("rhai"),
+        r#"export const ip = "203.0.113.9";"#,
+    )
+    .unwrap();
+    let code = format!("import {:?} as m; m::ip", module.display().to_string());
+    let err = get_ip(&code).await.unwrap_err().to_string();
+    let _ = std::fs::remove_file(module.with_extension("rhai"));
+    assert!(err.contains("steps[0] (Script) failed"), "{err}");
+    assert!(err.contains("not found"), "{err}");
+}
+
+#[tokio::test]
+async fn serves_metrics() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/ip/a" => MockResponse::new(200, "203.0.113.7"),
+        "/ip/broken" => MockResponse::new(200, "not an address"),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let sources = format!(
+        "[\"{}\"]\ntier = 1\n[\"{}\"]\ntier = 2\n",
+        server.url("/ip/broken"),
+        server.url("/ip/a"),
+    );
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        Ipv4Addr::new(203, 0, 113, 7)
+    );
+
+    let listener = crate::http_server::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let task = tokio::spawn(crate::http_server::serve(listener, crate::metrics::handle));
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body = response.text().await.unwrap();
+
+    let series = |name: &str, path: &str| format!("{name}{{source=\"{}\"}} 1\n", server.url(path));
+    assert!(
+        body.contains("# TYPE cloudflare_ddns_source_latency_seconds histogram"),
+        "{body}"
+    );
+    for name in [
+        "cloudflare_ddns_sync_attempts_total",
+        "cloudflare_ddns_update_attempts_total",
+    ] {
+        assert!(body.contains(&format!("# TYPE {name} counter")), "{body}");
+    }
+    assert!(
+        body.contains(&series(
+            "cloudflare_ddns_source_latency_seconds_count",
+            "/ip/a"
+        )),
+        "{body}"
+    );
+    assert!(
+        body.contains(&series(
+            "cloudflare_ddns_source_latency_seconds_count",
+            "/ip/broken"
+        )),
+        "{body}"
+    );
+    assert!(
+        body.contains(&series("cloudflare_ddns_source_errors_total", "/ip/broken")),
+        "{body}"
+    );
+    assert!(
+        !body.contains(&series("cloudflare_ddns_source_errors_total", "/ip/a")),
+        "{body}"
+    );
+
+    let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .post(format!("http://{addr}/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 405);
+    task.abort();
+}
+
+#[tokio::test]
+async fn serves_status() {
+    use crate::status::StatusApi;
+
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let cfg = config_for(&server).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let api = StatusApi::new(ctx.status.clone(), ctx.source_stats.clone(), &cfg);
+    let listener = crate::http_server::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let task = tokio::spawn(crate::http_server::serve(listener, move |req| {
+        api.handle(req)
+    }));
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    let status = || async {
+        get("/status")
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap()
+    };
+
+    assert_eq!(get("/healthz").await.unwrap().status(), 200);
+    assert_eq!(get("/readyz").await.unwrap().status(), 503);
+    assert!(status().await["ip"].is_null());
+
+    assert!(ctx.sync(cfg.clone()).await.unwrap());
+    assert_eq!(get("/readyz").await.unwrap().status(), 200);
+
+    let synced = status().await;
+    assert_eq!(synced["ip"], "203.0.113.7");
+    assert_eq!(synced["record"]["record"], RECORD);
+    assert_eq!(synced["record"]["content"], "203.0.113.7");
+    assert_eq!(synced["ready"], true);
+    assert!(synced["last_sync"].is_u64(), "{synced}");
+    assert!(synced["last_error"].is_null(), "{synced}");
+    assert_eq!(synced["sources"][0]["successes"], 1, "{synced}");
+    assert_eq!(synced["sources"][0]["demoted"], false, "{synced}");
+
+    // a failed sync is reported, while the last good one keeps us ready
+    let sources = format!("[\"{}\"]\n", server.url("/missing"));
+    let broken = config_with_sources(&server, &sources).await;
+    assert!(ctx.sync(broken).await.is_err());
+
+    let failed = status().await;
+    let error = failed["last_error"]["message"].as_str().unwrap();
+    assert!(
+        error.contains("none of the ip sources answered"),
+        "{failed}"
+    );
+    assert_eq!(failed["ready"], true);
+
+    // and cleared by the next good one
+    ctx.sync(cfg.clone()).await.unwrap();
+    let recovered = status().await;
+    assert!(recovered["last_error"].is_null(), "{recovered}");
+
+    assert_eq!(get("/nope").await.unwrap().status(), 404);
+    task.abort();
+}
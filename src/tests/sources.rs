@@ -0,0 +1,381 @@
+use super::{cloudflare, config_with_sources, try_config_with_sources};
+use crate::tests::mock_server::{MockResponse, MockServer};
+use crate::DdnsContext;
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{DNSClass, RData, Record, RecordType};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+
+/// answers `whoami.cloudflare` TXT CH queries with the address the query came from
+async fn dns_responder() -> SocketAddr {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let request = Message::from_vec(&buf[..len]).unwrap();
+            let query = request.queries()[0].clone();
+
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .add_query(query.clone());
+
+            match (query.name().to_ascii().as_str(), query.query_class()) {
+                ("whoami.cloudflare.", DNSClass::CH) => {
+                    let mut record = Record::from_rdata(
+                        query.name().clone(),
+                        0,
+                        RData::TXT(TXT::new(vec!["203.0.113.7".into()])),
+                    );
+                    record.set_dns_class(DNSClass::CH);
+                    response.add_answer(record);
+                }
+                _ => _ = response.set_response_code(ResponseCode::NXDomain),
+            }
+
+            assert_eq!(query.query_type(), RecordType::TXT);
+            let response = response.to_vec().unwrap();
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn resolves_over_dns() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let resolver = dns_responder().await;
+
+    let sources =
+        format!("[\"dns://{resolver}/whoami.cloudflare?type=TXT&class=CH\"]\nsteps = [\"Trim\"]\n");
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        Ipv4Addr::new(203, 0, 113, 7)
+    );
+
+    let sources = format!("[\"dns://{resolver}/myip.example.com?type=TXT\"]\nsteps = []\n");
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    let err = ctx.get_ip(&cfg).await.unwrap_err();
+    assert!(err.to_string().contains("Non-Existent Domain"), "{err}");
+}
+
+#[tokio::test]
+async fn rejects_invalid_sources() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    for source in [
+        "gopher://example.com/ip",
+        "dns://1.1.1.1/",
+        "dns://1.1.1.1/whoami.cloudflare?type=MX",
+        "dns://1.1.1.1/whoami.cloudflare?class=XX",
+        "dns://1.1.1.1/whoami.cloudflare?ttl=5",
+        "stun://stun.example.com:3478/path",
+        "stun:stun.example.com",
+        "natpmp://",
+        "pcp://192.168.1.1?lifetime=5",
+    ] {
+        let sources = format!("[\"{source}\"]\nsteps = []\n");
+        assert!(
+            try_config_with_sources(&server, &sources).await.is_err(),
+            "{source} was accepted"
+        );
+    }
+}
+
+/// answers binding requests with the address they came from, plus a bogus
+/// MAPPED-ADDRESS to make sure the XOR-MAPPED-ADDRESS wins
+async fn stun_responder() -> SocketAddr {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let request = &buf[..len];
+            assert_eq!(request[..4], [0x00, 0x01, 0x00, 0x00]);
+
+            let SocketAddr::V4(peer_v4) = peer else {
+                unreachable!()
+            };
+            let cookie = [0x21, 0x12, 0xA4, 0x42];
+            let port = (peer_v4.port() ^ 0x2112).to_be_bytes();
+            let ip = peer_v4.ip().octets();
+
+            let mut attrs = vec![0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x00, 0x00, 10, 0, 0, 1];
+            attrs.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01, port[0], port[1]]);
+            attrs.extend((0..4).map(|i| ip[i] ^ cookie[i]));
+
+            let mut response = vec![0x01, 0x01];
+            response.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+            response.extend_from_slice(&request[4..20]);
+            response.extend_from_slice(&attrs);
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn discovers_address_over_stun() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let stun = stun_responder().await;
+
+    let sources = format!("[\"stun://{stun}\"]\nsteps = []\n");
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    // the responder sees us on loopback
+    assert_eq!(ctx.get_ip(&cfg).await.unwrap(), Ipv4Addr::LOCALHOST);
+}
+
+const DEVICE_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+#[tokio::test]
+async fn asks_the_router_over_upnp() {
+    let server = MockServer::start(|req| match (&*req.method, &*req.target) {
+        ("GET", "/rootDesc.xml") => MockResponse::new(200, DEVICE_DESCRIPTION),
+        ("POST", "/ctl/IPConn") => {
+            assert_eq!(
+                req.header("soapaction"),
+                Some("\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"")
+            );
+            MockResponse::new(
+                200,
+                r#"<?xml version="1.0"?>
+                <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+                  <s:Body>
+                    <u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+                      <NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>
+                    </u:GetExternalIPAddressResponse>
+                  </s:Body>
+                </s:Envelope>"#,
+            )
+        }
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let description = server.url("/rootDesc.xml").replacen("http", "upnp", 1);
+    let sources = format!("[\"{description}\"]\nsteps = []\n");
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        Ipv4Addr::new(203, 0, 113, 7)
+    );
+}
+
+#[tokio::test]
+async fn limits_upnp_responses() {
+    let server = MockServer::start(|req| match &*req.target {
+        "/rootDesc.xml" => MockResponse::new(200, vec![b' '; 1024 * 1024]),
+        _ => cloudflare("198.51.100.1", "203.0.113.7")(req),
+    })
+    .await;
+
+    let description = server.url("/rootDesc.xml").replacen("http", "upnp", 1);
+    let sources = format!("[\"{description}\"]\nsteps = []\n");
+    let cfg = config_with_sources(&server, &sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let err = ctx.get_ip(&cfg).await.unwrap_err();
+    assert!(err.to_string().contains("65536 byte limit"), "{err}");
+}
+
+/// a router that speaks both nat-pmp and pcp
+async fn gateway_responder() -> SocketAddr {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1100];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let response = match &buf[..len] {
+                [0, 0] => vec![0, 128, 0, 0, 0, 0, 0, 42, 203, 0, 113, 7],
+                request @ [2, 1, ..] => {
+                    assert_eq!(request.len(), 60);
+                    // the client address has to be the one the request came from
+                    assert_eq!(
+                        request[8..24],
+                        Ipv4Addr::LOCALHOST.to_ipv6_mapped().octets()
+                    );
+
+                    let mut response = vec![2, 0x81, 0, 0, 0, 0, 0, 30, 0, 0, 0, 42];
+                    response.extend_from_slice(&[0; 12]);
+                    response.extend_from_slice(&request[24..40]);
+                    response.extend_from_slice(&request[40..44]);
+                    response.extend_from_slice(
+                        &Ipv4Addr::new(203, 0, 113, 8).to_ipv6_mapped().octets(),
+                    );
+                    response
+                }
+                _ => continue,
+            };
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn asks_the_router_over_natpmp_and_pcp() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let gateway = gateway_responder().await;
+
+    for (scheme, ip) in [
+        ("natpmp", Ipv4Addr::new(203, 0, 113, 7)),
+        ("pcp", Ipv4Addr::new(203, 0, 113, 8)),
+    ] {
+        let sources = format!("[\"{scheme}://{gateway}\"]\nsteps = []\n");
+        let cfg = config_with_sources(&server, &sources).await;
+        let ctx = DdnsContext::new(cfg.clone()).unwrap();
+        assert_eq!(ctx.get_ip(&cfg).await.unwrap(), ip);
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn reads_interface_addresses() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let loopback = if cfg!(target_os = "linux") {
+        "lo"
+    } else {
+        "lo0"
+    };
+
+    let get_ip = |fields: String| {
+        let server = &server;
+        async move {
+            let sources = format!("[local]\ninterface = \"{loopback}\"\n{fields}\n");
+            let cfg = config_with_sources(server, &sources).await;
+            let ctx = DdnsContext::new(cfg.clone()).unwrap();
+            let ip = ctx.get_ip(&cfg).await;
+            assert!(server.requests().is_empty());
+            ip
+        }
+    };
+
+    let ip = get_ip("global = false\nprefix = \"127.0.0.0/8\"".into()).await;
+    assert_eq!(ip.unwrap(), Ipv4Addr::LOCALHOST);
+
+    // `::1` is never picked
+    let ip = get_ip("global = false".into()).await;
+    assert_eq!(ip.unwrap(), Ipv4Addr::LOCALHOST);
+
+    let err = get_ip(String::new()).await.unwrap_err();
+    assert!(err.to_string().contains("no address that passes"), "{err}");
+
+    let err = get_ip("global = false\nprefix = \"10.0.0.0/8\"".into())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("no address that passes"), "{err}");
+
+    let sources = "[local]\ninterface = \"does-not-exist0\"\n";
+    let cfg = config_with_sources(&server, sources).await;
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    let err = ctx.get_ip(&cfg).await.unwrap_err();
+    assert!(err.to_string().contains("no interface"), "{err}");
+
+    for (fields, reason) in [
+        ("prefix = \"127.0.0.0/33\"", "at most 32"),
+        ("prefix = \"::1/128\"", "isn't an ipv4 block"),
+        ("ipv6 = true", "`ipv6` isn't supported"),
+        ("temporary = true", "`temporary` isn't supported"),
+    ] {
+        let sources = format!("[local]\ninterface = \"lo\"\n{fields}\n");
+        let err = try_config_with_sources(&server, &sources)
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("{fields} was accepted"));
+        assert!(format!("{err:#}").contains(reason), "{err:#}");
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn runs_commands_and_reads_files() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let get_ip = |source: String| {
+        let server = &server;
+        async move {
+            let cfg = config_with_sources(server, &source).await;
+            let ctx = DdnsContext::new(cfg.clone()).unwrap();
+            ctx.get_ip(&cfg).await
+        }
+    };
+
+    let ip = get_ip(
+        "[script]\nexec = [\"sh\", \"-c\", \"echo '  203.0.113.7'\"]\nsteps = [\"Trim\"]".into(),
+    )
+    .await;
+    assert_eq!(ip.unwrap(), Ipv4Addr::new(203, 0, 113, 7));
+
+    let err = get_ip("[script]\nexec = [\"sh\", \"-c\", \"echo oops >&2; exit 3\"]".into())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("oops"), "{err}");
+
+    let err = get_ip("[script]\nexec = [\"sleep\", \"5\"]\ntimeout = 00:00:00.200".into())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("timed out"), "{err}");
+
+    // endless output is cut off at the size limit rather than running into the timeout
+    let err = get_ip("[script]\nexec = [\"yes\"]\ntimeout = 00:00:30".into())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("byte limit"), "{err}");
+
+    let path = super::state_file().with_extension("ip");
+    std::fs::write(&path, "203.0.113.9\n").unwrap();
+    let ip = get_ip(format!(
+        "[router]\nfile = {:?}\nsteps = [\"Trim\"]",
+        path.display().to_string()
+    ))
+    .await;
+    assert_eq!(ip.unwrap(), Ipv4Addr::new(203, 0, 113, 9));
+    std::fs::remove_file(&path).unwrap();
+
+    let err = get_ip("[router]\nfile = \"/dev/zero\"".into())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("byte limit"), "{err}");
+
+    for source in [
+        "[script]\nexec = []",
+        "[script]\nexec = [\"true\"]\nfile = \"/tmp/ip\"",
+    ] {
+        assert!(
+            try_config_with_sources(&server, source).await.is_err(),
+            "{source} was accepted"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use super::{cloudflare, state_file, try_config, ZONE_ID};
+use crate::cloudflare::{CloudflareError, RecordQuery};
+use crate::tests::mock_server::MockServer;
+use crate::DdnsContext;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertifiedKey, DnType, IsCa, KeyPair,
+};
+use ring::digest::{digest, SHA256};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+/// a self-signed certificate valid for both `localhost` and `127.0.0.1`
+fn certificate() -> CertifiedKey {
+    rcgen::generate_simple_self_signed(["localhost".to_owned(), "127.0.0.1".to_owned()]).unwrap()
+}
+
+fn pin(key: &KeyPair) -> String {
+    let hash = digest(&SHA256, &key.public_key_der());
+    format!("sha256/{}", BASE64_STANDARD.encode(hash))
+}
+
+/// a ca certificate, signed by `issuer` or self-signed without one
+fn ca(name: &str, issuer: Option<&CertifiedKey>) -> CertifiedKey {
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, name);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+
+    let key_pair = KeyPair::generate().unwrap();
+    let cert = match issuer {
+        Some(issuer) => params.signed_by(&key_pair, &issuer.cert, &issuer.key_pair),
+        None => params.self_signed(&key_pair),
+    };
+    CertifiedKey {
+        cert: cert.unwrap(),
+        key_pair,
+    }
+}
+
+fn leaf(issuer: &CertifiedKey) -> CertifiedKey {
+    let params = CertificateParams::new(["localhost".to_owned(), "127.0.0.1".to_owned()]).unwrap();
+    let key_pair = KeyPair::generate().unwrap();
+    let cert = params
+        .signed_by(&key_pair, &issuer.cert, &issuer.key_pair)
+        .unwrap();
+    CertifiedKey { cert, key_pair }
+}
+
+async fn tls_server(cert: &CertifiedKey) -> MockServer {
+    chain_server(cert, &[]).await
+}
+
+/// serves `leaf` along with `chain`
+async fn chain_server(leaf: &CertifiedKey, chain: &[&Certificate]) -> MockServer {
+    let chain = std::iter::once(&leaf.cert)
+        .chain(chain.iter().copied())
+        .map(|cert| cert.der().clone())
+        .collect();
+
+    MockServer::start_tls(
+        chain,
+        leaf.key_pair.serialize_der(),
+        cloudflare("198.51.100.1", "203.0.113.7"),
+    )
+    .await
+}
+
+/// writes `contents` to a fresh ca-file
+fn ca_file(contents: &str) -> PathBuf {
+    let path = state_file().with_extension("pem");
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn http(ca_file: &Path, pins: &[String]) -> String {
+    format!(
+        r#"
+        [client]
+        max-retries = 3
+        retry-interval = 00:00:00.100
+        timeout = 00:00:05
+
+        [tls]
+        ca-file = {ca_file:?}
+        cloudflare-pins = {pins:?}
+        "#,
+        ca_file = ca_file.display().to_string(),
+    )
+}
+
+fn sources(url: &str) -> String {
+    format!("[\"{url}\"]\nsteps = [\"Plaintext\"]\n")
+}
+
+#[tokio::test]
+async fn accepts_matching_pin() {
+    let cert = certificate();
+    let server = tls_server(&cert).await;
+    let path = ca_file(&cert.cert.pem());
+
+    let cfg = try_config(
+        &server,
+        &http(&path, &[pin(&cert.key_pair)]),
+        &sources(&server.url("/ip")),
+    )
+    .await
+    .unwrap();
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    assert!(ctx.run_ddns(cfg).await.unwrap());
+    assert!(server.requests().iter().any(|req| req.method == "PATCH"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn accepts_pinned_ca() {
+    let root = ca("test root", None);
+    let intermediate = ca("test intermediate", Some(&root));
+    let cert = leaf(&intermediate);
+    let path = ca_file(&root.cert.pem());
+
+    // an intermediate that doesn't issue our leaf doesn't count, even when the server sends it along
+    let unrelated = ca("unrelated", None);
+    let cases = [
+        (&[&intermediate.cert][..], pin(&root.key_pair), true),
+        (&[&intermediate.cert], pin(&intermediate.key_pair), true),
+        (
+            &[&intermediate.cert, &unrelated.cert],
+            pin(&unrelated.key_pair),
+            false,
+        ),
+    ];
+
+    for (chain, pin, accepted) in cases {
+        let server = chain_server(&cert, chain).await;
+        let cfg = try_config(&server, &http(&path, &[pin]), &sources(&server.url("/ip")))
+            .await
+            .unwrap();
+        let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+        let records = ctx
+            .cloudflare(&cfg)
+            .list_records(ZONE_ID, &RecordQuery::default())
+            .await;
+        match accepted {
+            true => assert_eq!(records.unwrap().len(), 1),
+            false => assert!(
+                matches!(records, Err(CloudflareError::PinMismatch(_))),
+                "{records:?}"
+            ),
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_wrong_pin_once() {
+    let cert = certificate();
+    let server = tls_server(&cert).await;
+    let path = ca_file(&cert.cert.pem());
+
+    // the ip source reaches the same server under another host, which isn't pinned
+    let wrong = pin(&KeyPair::generate().unwrap());
+    let cfg = try_config(
+        &server,
+        &http(&path, &[wrong]),
+        &sources(&format!("https://{}/ip", server.addr())),
+    )
+    .await
+    .unwrap();
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+
+    let err = ctx
+        .cloudflare(&cfg)
+        .list_records(ZONE_ID, &RecordQuery::default())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, CloudflareError::PinMismatch(_)), "{err}");
+    assert!(err.to_string().contains(&pin(&cert.key_pair)[7..]), "{err}");
+    assert_eq!(server.connections(), 1);
+    assert!(server.requests().is_empty());
+
+    assert_eq!(
+        ctx.get_ip(&cfg).await.unwrap(),
+        Ipv4Addr::new(203, 0, 113, 7)
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn trusts_only_the_ca_file() {
+    let cert = certificate();
+    let server = tls_server(&cert).await;
+    let path = ca_file(&certificate().cert.pem());
+
+    // a ca-file with some other certificate doesn't make ours trusted
+    let cfg = try_config(&server, &http(&path, &[]), &sources(&server.url("/ip")))
+        .await
+        .unwrap();
+    let ctx = DdnsContext::new(cfg.clone()).unwrap();
+    assert!(ctx.get_ip(&cfg).await.is_err());
+    assert!(server.requests().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_invalid_tls_config() {
+    let server = MockServer::start(cloudflare("198.51.100.1", "203.0.113.7")).await;
+    let cert = certificate().cert.pem();
+    let sources = sources(&server.url("/ip"));
+
+    for (contents, pins, reason) in [
+        ("not a certificate", vec![], "contains no certificates"),
+        (
+            "-----BEGIN CERTIFICATE-----\n!!!!\n-----END CERTIFICATE-----\n",
+            vec![],
+            "invalid pem in ca-file",
+        ),
+        (&*cert, vec!["md5/AAAA".to_owned()], "sha256/<base64>"),
+        (&*cert, vec!["sha256/AAAA".to_owned()], "invalid sha256 pin"),
+    ] {
+        let path = ca_file(contents);
+        let err = try_config(&server, &http(&path, &pins), &sources)
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("{contents} with {pins:?} was accepted"));
+        assert!(format!("{err:#}").contains(reason), "{err:#}");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    let missing = state_file().with_extension("pem");
+    let err = try_config(&server, &http(&missing, &[]), &sources)
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("unable to read ca-file"),
+        "{err:#}"
+    );
+}
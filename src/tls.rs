@@ -0,0 +1,234 @@
+use crate::config::http::{SpkiPin, TlsConfig};
+use anyhow::{Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use ring::digest::{digest, SHA256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring as provider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore,
+    SignatureScheme,
+};
+use std::error::Error as StdError;
+use std::io;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone)]
+#[error("certificate pin mismatch for {host}: no key on the chain of sha256/{found} is in cloudflare-pins")]
+pub struct PinMismatch {
+    host: Box<str>,
+    found: Box<str>,
+}
+
+impl PinMismatch {
+    /// walks an error chain looking for a pin mismatch,
+    /// this has to dig through io errors and rustls errors as neither of them expose their inner error as a source
+    pub fn find<'a>(err: &'a (dyn StdError + 'static)) -> Option<&'a PinMismatch> {
+        let mut next = Some(err);
+        while let Some(err) = next {
+            if let Some(pin) = err.downcast_ref::<PinMismatch>() {
+                return Some(pin);
+            }
+
+            if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(
+                other,
+            )))) = err.downcast_ref::<rustls::Error>()
+            {
+                return Self::find(&**other);
+            }
+
+            if let Some(inner) = err.downcast_ref::<io::Error>().and_then(io::Error::get_ref) {
+                return Self::find(inner);
+            }
+
+            next = err.source();
+        }
+
+        None
+    }
+}
+
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>,
+    algorithms: WebPkiSupportedAlgorithms,
+    /// the only host we pin, ip sources are allowed to rotate their keys freely
+    host: Box<str>,
+    pins: Box<[SpkiPin]>,
+}
+
+/// wraps the contents of a SubjectPublicKeyInfo back into its DER SEQUENCE,
+/// trust anchors only keep the contents
+fn der_sequence(contents: &[u8]) -> Vec<u8> {
+    let len = contents.len().to_be_bytes();
+    let significant = &len[len.iter().take_while(|&&b| b == 0).count()..];
+
+    let mut der = vec![0x30];
+    match contents.len() {
+        short @ 0..0x80 => der.push(short as u8),
+        _ => {
+            der.push(0x80 | significant.len() as u8);
+            der.extend_from_slice(significant);
+        }
+    }
+    der.extend_from_slice(contents);
+    der
+}
+
+impl PinnedVerifier {
+    fn is_pinned(&self, spki: &[u8]) -> bool {
+        let hash = digest(&SHA256, spki);
+        self.pins.iter().any(|pin| pin.0 == hash.as_ref())
+    }
+
+    /// accepts the connection if any certificate on a verified chain, from the leaf up to the
+    /// trust anchor, has a pinned key, so pinning an intermediate or ca survives leaf rotation
+    fn check_pin(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<(), rustls::Error> {
+        let cert = webpki::EndEntityCert::try_from(end_entity)
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+
+        // path building tries every candidate chain until one passes this check
+        let pinned_path = |path: &webpki::VerifiedPath<'_>| {
+            let leaf = path.end_entity().subject_public_key_info();
+            let anchor = der_sequence(&path.anchor().subject_public_key_info);
+
+            let pinned = self.is_pinned(&leaf)
+                || self.is_pinned(&anchor)
+                || path
+                    .intermediate_certificates()
+                    .any(|cert| self.is_pinned(&cert.subject_public_key_info()));
+
+            match pinned {
+                true => Ok(()),
+                false => Err(webpki::Error::UnknownIssuer),
+            }
+        };
+
+        let verified = cert.verify_for_usage(
+            self.algorithms.all,
+            &self.roots.roots,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+            Some(&pinned_path),
+        );
+
+        if verified.is_ok() {
+            return Ok(());
+        }
+
+        let mismatch = PinMismatch {
+            host: self.host.clone(),
+            found: BASE64_STANDARD
+                .encode(digest(&SHA256, &cert.subject_public_key_info()))
+                .into_boxed_str(),
+        };
+
+        Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+            OtherError(Arc::new(mismatch)),
+        )))
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = matches!(
+            server_name,
+            ServerName::DnsName(name) if name.as_ref().eq_ignore_ascii_case(&self.host)
+        );
+
+        if pinned && !self.pins.is_empty() {
+            self.check_pin(end_entity, intermediates, now)?;
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// builds a rustls config trusting the native roots, the configured ca-file,
+/// and enforcing the cloudflare api pins on `api_host` if there are any
+pub fn client_config(cfg: &TlsConfig, api_host: &str) -> Result<ClientConfig> {
+    let provider = Arc::new(provider::default_provider());
+
+    let mut roots = RootCertStore::empty();
+    // a broken native cert shouldn't stop us from trusting the rest
+    let native = rustls_native_certs::load_native_certs().unwrap_or_default();
+    roots.add_parsable_certificates(native);
+
+    for cert in cfg.ca_certs() {
+        roots
+            .add(CertificateDer::from(cert.to_vec()))
+            .context("invalid certificate in ca-file")?;
+    }
+
+    let roots = Arc::new(roots);
+    let inner =
+        WebPkiServerVerifier::builder_with_provider(Arc::clone(&roots), Arc::clone(&provider))
+            .build()
+            .context("unable to build the certificate verifier")?;
+
+    let verifier = PinnedVerifier {
+        inner,
+        roots,
+        algorithms: provider.signature_verification_algorithms,
+        host: api_host.into(),
+        pins: cfg.cloudflare_pins().into(),
+    };
+
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("unable to select tls versions")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(config)
+}
@@ -29,7 +29,6 @@ pub struct UpdaterExit {
 
 impl UpdaterExit {
     /// returns the name of the exited service
-    #[allow(dead_code)] // future flexibility
     pub fn name(&self) -> &str {
         self.name
     }
@@ -1,5 +1,4 @@
 use std::convert::Infallible;
-use std::fmt::{Display, Formatter, Write};
 use std::net::{self, Ipv4Addr};
 use std::num::NonZero;
 use std::path::{Path, PathBuf};
@@ -81,46 +80,6 @@ pub fn new_skip_interval_at(start: Instant, period: Duration) -> Interval {
     interval
 }
 
-pub struct EscapeJson<'a>(&'a str);
-
-impl<'a> Display for EscapeJson<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut utf16_buf = [0u16; 2];
-        for c in self.0.chars() {
-            match c {
-                '\x08' => f.write_str("\\b"),
-                '\x0c' => f.write_str("\\f"),
-                '\n' => f.write_str("\\n"),
-                '\r' => f.write_str("\\r"),
-                '\t' => f.write_str("\\t"),
-                '"' => f.write_str("\\\""),
-                '\\' => f.write_str("\\"),
-                ' ' => f.write_char(' '),
-                c if c.is_ascii_graphic() => f.write_char(c),
-                c => {
-                    let encoded = c.encode_utf16(&mut utf16_buf);
-                    for utf16 in encoded {
-                        write!(f, "\\u{:04X}", utf16)?;
-                    }
-                    Ok(())
-                }
-            }?
-        }
-
-        Ok(())
-    }
-}
-
-pub trait EscapeExt {
-    fn escape_json(&self) -> EscapeJson<'_>;
-}
-
-impl EscapeExt for str {
-    fn escape_json(&self) -> EscapeJson<'_> {
-        EscapeJson(self)
-    }
-}
-
 #[derive(Debug, Error)]
 pub enum AddrParseError {
     #[error("The input data was too long to even be considered an address")]